@@ -0,0 +1,151 @@
+//! Signs plugin libraries for `PluginManager::with_trusted_keys`.
+//!
+//! ```bash
+//! # Writes the secret key and prints the public key to trust in the host.
+//! plugin_signer keygen plugins.key
+//! # Writes target/release/libplugin_a.so.sig next to the library.
+//! plugin_signer sign plugins.key target/release/libplugin_a.so
+//! # Checks a signature the way the host will.
+//! plugin_signer verify <public key> target/release/libplugin_a.so
+//! ```
+
+use ed25519_dalek::{Signer, SigningKey};
+use plugin_manager::plugin_signature::{
+    check_signature, decode_hex, encode_hex, public_key_from_hex, signature_path,
+};
+use std::error::Error;
+use std::path::Path;
+use std::{env, fs, process};
+
+const USAGE: &str = "\
+usage: plugin_signer keygen <secret key file>
+       plugin_signer public-key <secret key file>
+       plugin_signer sign <secret key file> <library>...
+       plugin_signer verify <public key> <library>...";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["keygen", key_file] => keygen(Path::new(key_file)),
+        ["public-key", key_file] => read_signing_key(Path::new(key_file)).map(|key| {
+            println!("{}", encode_hex(key.verifying_key().as_bytes()));
+        }),
+        ["sign", key_file, libraries @ ..] if !libraries.is_empty() => {
+            sign(Path::new(key_file), libraries)
+        }
+        ["verify", public_key, libraries @ ..] if !libraries.is_empty() => {
+            verify(public_key, libraries)
+        }
+        _ => {
+            eprintln!("{USAGE}");
+            process::exit(2);
+        }
+    };
+    if let Err(error) = result {
+        eprintln!("plugin_signer: {error}");
+        process::exit(1);
+    }
+}
+
+fn keygen(key_file: &Path) -> Result<(), Box<dyn Error>> {
+    if key_file.exists() {
+        return Err(format!("{} already exists", key_file.display()).into());
+    }
+    let mut seed = [0u8; 32];
+    getrandom::getrandom(&mut seed)?;
+    let key = SigningKey::from_bytes(&seed);
+    write_secret(key_file, &encode_hex(&seed))?;
+    println!("{}", encode_hex(key.verifying_key().as_bytes()));
+    Ok(())
+}
+
+fn sign(key_file: &Path, libraries: &[&str]) -> Result<(), Box<dyn Error>> {
+    let key = read_signing_key(key_file)?;
+    for library in libraries {
+        let library = Path::new(library);
+        let signature = key.sign(&fs::read(library)?);
+        let sig_path = signature_path(library);
+        fs::write(&sig_path, encode_hex(&signature.to_bytes()) + "\n")?;
+        println!("Signed {} -> {}", library.display(), sig_path.display());
+    }
+    Ok(())
+}
+
+fn verify(public_key: &str, libraries: &[&str]) -> Result<(), Box<dyn Error>> {
+    let key = public_key_from_hex(public_key)?;
+    for library in libraries {
+        let library = Path::new(library);
+        check_signature(&fs::read(library)?, library, &[key])
+            .map_err(|reason| format!("{}: {reason}", library.display()))?;
+        println!("{}: OK", library.display());
+    }
+    Ok(())
+}
+
+fn read_signing_key(key_file: &Path) -> Result<SigningKey, Box<dyn Error>> {
+    let seed: [u8; 32] = decode_hex(fs::read_to_string(key_file)?.trim())
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| format!("{} is not a hex encoded secret key", key_file.display()))?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+#[cfg(unix)]
+fn write_secret(path: &Path, contents: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    writeln!(file, "{contents}")
+}
+
+#[cfg(not(unix))]
+fn write_secret(path: &Path, contents: &str) -> std::io::Result<()> {
+    fs::write(path, format!("{contents}\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_and_verify_test() {
+        let directory = env::temp_dir().join(format!("plugin_signer-{}", process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let key_file = directory.join("plugins.key");
+        let library = directory.join("libplugin_a.so");
+        let library_name = library.to_str().unwrap();
+        fs::write(&library, b"\x7fELF plugin bytes").unwrap();
+
+        keygen(&key_file).unwrap();
+        assert!(
+            keygen(&key_file).is_err(),
+            "keygen must not overwrite a key"
+        );
+        let public_key = encode_hex(
+            read_signing_key(&key_file)
+                .unwrap()
+                .verifying_key()
+                .as_bytes(),
+        );
+
+        sign(&key_file, &[library_name]).unwrap();
+        assert!(signature_path(&library).exists());
+        verify(&public_key, &[library_name]).unwrap();
+
+        fs::write(&library, b"\x7fELF tampered bytes").unwrap();
+        let error = verify(&public_key, &[library_name]).unwrap_err();
+        assert!(error.to_string().contains(library_name), "{error}");
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
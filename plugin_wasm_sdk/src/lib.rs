@@ -0,0 +1,224 @@
+//! Guest side of the plugin manager's WebAssembly plugin interface.
+//!
+//! Implement [`WasmPlugin`] and export it with [`export_wasm_plugin!`], then
+//! build the crate for `wasm32-unknown-unknown`:
+//!
+//! ```rust
+//! use plugin_wasm_sdk::WasmPlugin;
+//!
+//! #[derive(Default)]
+//! struct Greeter;
+//!
+//! impl WasmPlugin for Greeter {
+//!     fn name(&self) -> String {
+//!         "greeter".to_string()
+//!     }
+//!
+//!     fn execute(&self, context: &[u8]) -> Result<(), String> {
+//!         if context.is_empty() {
+//!             return Err("expected a name".to_string());
+//!         }
+//!         Ok(())
+//!     }
+//! }
+//!
+//! plugin_wasm_sdk::export_wasm_plugin!(Greeter);
+//! ```
+//!
+//! ```toml
+//! [lib]
+//! crate-type = ["cdylib"]
+//! ```
+//!
+//! ```bash
+//! cargo build --release --target wasm32-unknown-unknown
+//! ```
+//!
+//! The resulting `.wasm` file is listed in the plugin metadata like any other
+//! plugin library and loaded by a host built with the `wasm` feature.
+
+/// A plugin compiled to WebAssembly.
+///
+/// The context passed to `PluginManager::execute_plugin` arrives as bytes:
+/// strings as UTF-8, `serde_json::Value` as serialized JSON and `()` as an
+/// empty slice. A panic traps the module and is reported to the host as a
+/// `PluginError::WasmTrap`.
+pub trait WasmPlugin {
+    fn name(&self) -> String;
+
+    fn execute(&self, context: &[u8]) -> Result<(), String>;
+
+    /// Registers the plugin as an inventory plugin when true.
+    fn is_inventory(&self) -> bool {
+        false
+    }
+
+    /// Called by `PluginInventory::load` for inventory plugins.
+    fn load(&self) {}
+
+    /// Called by `Plugin::on_unload` before the module is dropped.
+    fn on_unload(&self) {}
+}
+
+/// Exports the WASM plugin interface for a [`WasmPlugin`].
+///
+/// The plugin is created on first use, either with `Default::default()` or
+/// with the given expression.
+#[macro_export]
+macro_rules! export_wasm_plugin {
+    ($plugin:ty) => {
+        $crate::export_wasm_plugin!($plugin, <$plugin as ::core::default::Default>::default());
+    };
+    ($plugin:ty, $create:expr) => {
+        fn __wasm_plugin() -> &'static $plugin {
+            static PLUGIN: ::std::sync::OnceLock<$plugin> = ::std::sync::OnceLock::new();
+            PLUGIN.get_or_init(|| $create)
+        }
+
+        #[unsafe(no_mangle)]
+        pub extern "C" fn plugin_alloc(len: i32) -> i32 {
+            $crate::__private::alloc(len)
+        }
+
+        #[unsafe(no_mangle)]
+        pub extern "C" fn plugin_dealloc(ptr: i32, len: i32) {
+            $crate::__private::dealloc(ptr, len)
+        }
+
+        #[unsafe(no_mangle)]
+        pub extern "C" fn plugin_name() -> i64 {
+            $crate::__private::pack_string($crate::WasmPlugin::name(__wasm_plugin()))
+        }
+
+        #[unsafe(no_mangle)]
+        pub extern "C" fn plugin_kind() -> i32 {
+            $crate::WasmPlugin::is_inventory(__wasm_plugin()) as i32
+        }
+
+        #[unsafe(no_mangle)]
+        pub extern "C" fn plugin_execute(ptr: i32, len: i32) -> i64 {
+            $crate::__private::execute(__wasm_plugin(), ptr, len)
+        }
+
+        #[unsafe(no_mangle)]
+        pub extern "C" fn plugin_load() {
+            $crate::WasmPlugin::load(__wasm_plugin())
+        }
+
+        #[unsafe(no_mangle)]
+        pub extern "C" fn plugin_unload() {
+            $crate::WasmPlugin::on_unload(__wasm_plugin())
+        }
+    };
+}
+
+/// Memory handling used by [`export_wasm_plugin!`]. Pointers are 32-bit
+/// offsets into the module's linear memory.
+#[doc(hidden)]
+pub mod __private {
+    use super::WasmPlugin;
+
+    /// Allocates a buffer the host writes into; ownership passes back to the
+    /// guest when the buffer is handed to `execute`.
+    pub fn alloc(len: i32) -> i32 {
+        into_raw(vec![0u8; len as usize].into_boxed_slice()) as usize as i32
+    }
+
+    /// Frees a buffer previously returned to the host.
+    pub fn dealloc(ptr: i32, len: i32) {
+        drop(unsafe { take(ptr, len) });
+    }
+
+    /// Hands a string to the host as `ptr << 32 | len`; the host frees it
+    /// with `dealloc`.
+    pub fn pack_string(value: String) -> i64 {
+        let value = value.into_bytes().into_boxed_slice();
+        let len = value.len() as i32;
+        pack(into_raw(value) as usize as i32, len)
+    }
+
+    pub fn execute(plugin: &dyn WasmPlugin, ptr: i32, len: i32) -> i64 {
+        let context = unsafe { take(ptr, len) };
+        match plugin.execute(&context) {
+            Ok(()) => 0,
+            Err(message) => pack_string(message),
+        }
+    }
+
+    fn pack(ptr: i32, len: i32) -> i64 {
+        (((ptr as u32 as u64) << 32) | len as u32 as u64) as i64
+    }
+
+    /// Takes ownership of a buffer created by `alloc` or `pack_string`.
+    unsafe fn take(ptr: i32, len: i32) -> Box<[u8]> {
+        unsafe { from_raw(ptr as usize as *mut u8, len as usize) }
+    }
+
+    fn into_raw(buffer: Box<[u8]>) -> *mut u8 {
+        Box::into_raw(buffer) as *mut u8
+    }
+
+    unsafe fn from_raw(ptr: *mut u8, len: usize) -> Box<[u8]> {
+        unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)) }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// Splits a packed value the way the host does.
+        fn unpack(packed: i64) -> (i32, i32) {
+            ((packed >> 32) as i32, packed as i32)
+        }
+
+        #[test]
+        fn pack_test() {
+            assert_eq!(pack(0, 0), 0);
+            assert_eq!(pack(1, 2), (1 << 32) | 2);
+            assert_eq!(unpack(pack(1024, 17)), (1024, 17));
+            // Offsets above 2 GiB are negative as `i32` but must not sign-extend.
+            let ptr = 0x8000_0010_u32 as i32;
+            let len = 0xffff_fff0_u32 as i32;
+            assert_eq!(unpack(pack(ptr, len)), (ptr, len));
+            assert_eq!(pack(0, len) >> 32, 0);
+        }
+
+        #[test]
+        fn raw_buffer_round_trip_test() {
+            let ptr = into_raw(b"context".to_vec().into_boxed_slice());
+            let buffer = unsafe { from_raw(ptr, 7) };
+            assert_eq!(&buffer[..], b"context");
+
+            let ptr = into_raw(Box::default());
+            assert!(unsafe { from_raw(ptr, 0) }.is_empty());
+        }
+
+        // Guest pointers only fit the `i32` ABI on 32-bit targets such as
+        // `wasm32-unknown-unknown`.
+        #[cfg(target_pointer_width = "32")]
+        #[test]
+        fn alloc_round_trip_test() {
+            struct Echo;
+
+            impl WasmPlugin for Echo {
+                fn name(&self) -> String {
+                    "echo".to_string()
+                }
+
+                fn execute(&self, context: &[u8]) -> Result<(), String> {
+                    Err(String::from_utf8_lossy(context).into_owned())
+                }
+            }
+
+            let ptr = alloc(5);
+            unsafe { std::slice::from_raw_parts_mut(ptr as usize as *mut u8, 5) }
+                .copy_from_slice(b"hello");
+            let (ptr, len) = unpack(execute(&Echo, ptr, 5));
+            assert_eq!(unsafe { &take(ptr, len)[..] }, b"hello");
+
+            let (ptr, len) = unpack(pack_string("echo".to_string()));
+            assert_eq!(len, 4);
+            dealloc(ptr, len);
+        }
+    }
+}
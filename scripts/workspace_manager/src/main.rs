@@ -10,6 +10,8 @@ const REQUIRED_MEMBERS: &[&str] = &[
     "tests/plugin_inventory",
     "tests/plugin_mods",
     "tests/plugin_tasks",
+    "plugin_wasm_sdk",
+    "plugin_signer",
 ];
 
 const MANIFEST_PATHS: &[&str] = &[
@@ -17,6 +19,7 @@ const MANIFEST_PATHS: &[&str] = &[
     "tests/plugin_tasks/Cargo.toml",
     "tests/plugin_mods/Cargo.toml",
     "tests/plugin_inventory/Cargo.toml",
+    "plugin_signer/Cargo.toml",
 ];
 
 struct WorkspaceContext {
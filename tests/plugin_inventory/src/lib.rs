@@ -1,6 +1,8 @@
 pub mod inventory_a;
 use plugin_manager::plugin_types::Plugins;
 
+plugin_manager::declare_plugin_abi!();
+
 #[unsafe(no_mangle)]
 pub fn create_plugins() -> Vec<Plugins> {
     let plugins = vec![Plugins::Inventory(Box::new(inventory_a::InventoryA))];
@@ -1,6 +1,9 @@
 pub mod plugin_a;
 pub mod plugin_b;
 use plugin_manager::plugin_types::Plugins;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+plugin_manager::declare_plugin_abi!();
 
 #[unsafe(no_mangle)]
 pub fn create_plugins() -> Vec<Plugins> {
@@ -10,3 +13,13 @@ pub fn create_plugins() -> Vec<Plugins> {
     ];
     plugins
 }
+
+/// Number of plugins handed back through `destroy_plugins`, read by the host tests.
+#[unsafe(no_mangle)]
+pub static DESTROYED_PLUGINS: AtomicUsize = AtomicUsize::new(0);
+
+#[unsafe(no_mangle)]
+pub fn destroy_plugins(plugins: Vec<Plugins>) {
+    DESTROYED_PLUGINS.fetch_add(plugins.len(), Ordering::SeqCst);
+    drop(plugins);
+}
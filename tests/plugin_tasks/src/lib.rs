@@ -1,7 +1,12 @@
 pub mod task_a;
 use plugin_manager::plugin_types::Plugins;
 
-#[unsafe(no_mangle)]
+// Exported through the stable C ABI, so this library can be loaded by hosts
+// built with a different compiler version.
+plugin_manager::export_ffi_plugins!(create_plugins);
+// Registered for hosts that link this crate statically instead.
+plugin_manager::register_static_plugins!(create_plugins);
+
 pub fn create_plugins() -> Vec<Plugins> {
     let plugins = vec![Plugins::Base(Box::new(task_a::TaskA))];
     plugins
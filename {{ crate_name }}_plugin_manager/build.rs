@@ -0,0 +1,33 @@
+use std::process::Command;
+
+/// Records the version of the compiler building this crate so plugins and
+/// hosts can compare it during the ABI handshake, and the target directory
+/// and profile it is built into for the `{target_dir}` and `{profile}` path
+/// placeholders. Those are build machine paths, used when `CARGO_TARGET_DIR`
+/// is not set at runtime.
+fn main() {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+    let version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|version| version.trim().to_string())
+        .unwrap_or_else(|| String::from("unknown"));
+
+    println!("cargo:rustc-env=PLUGIN_MANAGER_RUSTC_VERSION={version}");
+
+    // OUT_DIR is <target dir>/<profile>/build/<package>-<hash>/out.
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    let mut ancestors = out_dir.ancestors().skip(3);
+    if let (Some(profile), Some(target_dir)) = (ancestors.next(), ancestors.next()) {
+        let profile = profile.file_name().unwrap_or_default().to_string_lossy();
+        println!("cargo:rustc-env=PLUGIN_MANAGER_PROFILE={profile}");
+        println!(
+            "cargo:rustc-env=PLUGIN_MANAGER_TARGET_DIR={}",
+            target_dir.display()
+        );
+    }
+    println!("cargo:rerun-if-env-changed=RUSTC");
+    println!("cargo:rerun-if-changed=build.rs");
+}
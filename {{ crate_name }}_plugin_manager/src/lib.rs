@@ -10,9 +10,15 @@
 //!
 //! - Dynamic loading of plugins from shared object files (.so)
 //! - Support for individual and grouped plugins
-//! - Plugin registration and deregistration
+//! - Plugin registration and deregistration, closing libraries once their last plugin is removed
 //! - Execution of plugin functionality
 //! - Metadata-driven plugin configuration
+//! - Sandboxed WebAssembly plugins with the `wasm` feature
+//! - Plugins linked statically into the host, see [`plugin_static`]
+//! - Lazy loading of libraries on first use, see [`plugin_lazy`]
+//! - Ed25519 signature verification of libraries, see [`plugin_signature`]
+//! - Pre-flight ELF inspection with precise diagnostics, see [`plugin_inspect`]
+//! - Per-plugin native library search paths, see [`plugin_deps`]
 //!
 //!
 //! ## Creating Plugins
@@ -41,8 +47,37 @@
 //! pub fn create_plugins() -> Vec<Box<dyn Plugin>> {
 //!     vec![Box::new(MyPlugin)]
 //! }
+//!
+//! // Every plugin library must export its ABI descriptor.
+//! plugin_manager::declare_plugin_abi!();
+//! ```
+//!
+//! The descriptor records the `plugin_manager` crate version, the rustc version
+//! and the `Plugins` layout version the plugin was built with. `load_plugin`
+//! validates it before calling `create_plugins` and rejects incompatible
+//! libraries with a `PluginError::AbiMismatch` instead of invoking them.
+//!
+//! ### Stable C ABI
+//!
+//! The Rust ABI above is only sound when the host and the plugin are built by the
+//! same compiler. Plugins can instead be exported through the `#[repr(C)]`
+//! interface in [`plugin_ffi`], which needs no ABI descriptor:
+//!
+//! ```rust
+//! use plugin_manager::plugin_types::Plugins;
+//!
+//! fn create_plugins() -> Vec<Plugins> {
+//!     Vec::new()
+//! }
+//!
+//! plugin_manager::export_ffi_plugins!(create_plugins);
 //! ```
 //!
+//! The host wraps these plugins so they still implement `Plugin` and
+//! `PluginInventory`. Execution contexts are sent as bytes, so only `()`,
+//! `String`, `&str`, `Vec<u8>` and `serde_json::Value` contexts are supported;
+//! the plugin receives them as a `Vec<u8>`.
+//!
 //! ## Setting up Cargo.toml for Plugins
 //!
 //! When creating a plugin, you need to set up your `Cargo.toml` file correctly:
@@ -170,19 +205,55 @@
 //! plugin_c = "/path/to/plugin_c.so"
 //! ```
 //!
+//! A table with a `path` key is a detailed entry, which can also be disabled,
+//! ordered, checked against a version requirement and given host-defined
+//! settings, see [`PluginManager::get_entry_config`]:
+//!
+//! ```toml
+//! [package.metadata.plugins.plugin_a]
+//! path = "/path/to/plugin_a.so"
+//! enabled = true
+//! priority = 10
+//! version = "^1.2"
+//! config = { endpoint = "https://example.com" }
+//! ```
+//!
+//! Libraries call their exported `create_plugins` function by default. An entry
+//! can name a different entry point instead, and use `mode = "single"` for
+//! factories returning one plugin (`fn() -> Box<dyn Plugin>`):
+//!
+//! ```toml
+//! [package.metadata.plugins]
+//! plugin_a = { path = "/path/to/plugin_a.so", entry_point = "create_plugin", mode = "single" }
+//! ```
+//!
+//! On Unix an entry can also set the `dlopen` flags its library is opened
+//! with, see [`plugin_dlopen`]:
+//!
+//! ```toml
+//! [package.metadata.plugins]
+//! plugin_a = { path = "/path/to/plugin_a.so", flags = ["global", "now"] }
+//! ```
+//!
+//! An entry can pin its library with a `sha256` digest. The file is hashed
+//! before it is opened and a mismatch fails with a
+//! `PluginError::IntegrityMismatch` showing both digests:
+//!
+//! ```toml
+//! [package.metadata.plugins]
+//! plugin_a = { path = "/path/to/plugin_a.so", sha256 = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08" }
+//! ```
+//!
 //! ## Usage
 //!
 //! Here's a basic example of how to use the `PluginManager`:
 //!
 //! ```rust
-//! # unsafe {
-//! #     std::env::set_var("CARGO_MANIFEST_PATH", "../tests/plugin_mods/Cargo.toml");
-//! # }
 //! use plugin_manager::PluginManager;
 //!
 //! # fn doc_test() -> Result<(), Box<dyn std::error::Error>> {
-//! // Create a new PluginManager
-//! let mut plugin_manager = PluginManager::new();
+//! // Create a PluginManager configured by a Cargo manifest
+//! let mut plugin_manager = PluginManager::from_manifest("../tests/plugin_mods/Cargo.toml");
 //!
 //! // Activate plugins based on metadata in Cargo.toml
 //! plugin_manager = plugin_manager.activate_plugins()?;
@@ -206,29 +277,78 @@
 //! This module provides a robust foundation for building plugin-based architectures
 //! in Rust applications, offering flexibility and ease of use.
 
+pub mod plugin_abi;
+pub mod plugin_config;
+pub mod plugin_deps;
+pub mod plugin_discovery;
+pub mod plugin_dlopen;
+pub mod plugin_errors;
+pub mod plugin_ffi;
+pub mod plugin_inspect;
+#[cfg(unix)]
+pub mod plugin_isolation;
+pub mod plugin_layers;
+pub mod plugin_lazy;
+pub mod plugin_reload;
+pub mod plugin_shadow;
+pub mod plugin_signature;
+pub mod plugin_static;
 pub mod plugin_structs;
 pub mod plugin_types;
+#[cfg(feature = "wasm")]
+pub mod plugin_wasm;
 // pub use plugin_types;
+pub use inventory;
 
-use libloading::{Library, Symbol};
-use plugin_structs::{PluginCreate as PluginCreateNew, PluginResult as PluginResultNew};
-use plugin_types::{GroupOrName, Plugin, PluginEntry, PluginInventory, PluginName, Plugins};
+use libloading::Symbol;
+use plugin_structs::{
+    LibraryId, LoadedLibrary, PluginCreate as PluginCreateNew, PluginFactory,
+    PluginResult as PluginResultNew,
+};
+use plugin_types::{
+    EntryMode, GroupOrName, Plugin, PluginEntry, PluginInventory, PluginName, PluginSpec, Plugins,
+};
 use serde::Deserialize;
 use std::any::Any;
 use std::collections::{HashMap, hash_map};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 // use std::error::Error;
 use std::io::{Error, ErrorKind};
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, Default)]
 pub struct Metadata {
     pub plugins: Option<HashMap<GroupOrName, PluginEntry>>,
+    /// The plugins provided by each entry, used by lazy loading.
+    pub plugin_index: Option<HashMap<GroupOrName, Vec<PluginName>>>,
 }
 
 pub struct PluginManager {
     pub plugins: HashMap<PluginName, Plugins>,
+    config_source: plugin_config::ConfigSource,
+    /// `config` tables of the activated entries.
+    entry_configs: HashMap<GroupOrName, serde_json::Value>,
+    /// Layers the activated entries came from, for layered sources.
+    entry_origins: HashMap<GroupOrName, plugin_layers::ConfigOrigin>,
     plugin_path: Vec<HashMap<GroupOrName, PluginEntry>>,
-    libraries: Vec<libloading::Library>, // Add this to keep libraries alive
+    libraries: HashMap<LibraryId, LoadedLibrary>,
+    plugin_libraries: HashMap<PluginName, LibraryId>,
+    /// Plugin names in registration order, unloaded in reverse.
+    registration_order: Vec<PluginName>,
+    next_library_id: LibraryId,
+    hot_reload: bool,
+    /// Where reloaded libraries are copied to, private to this manager.
+    reload_dir: PathBuf,
+    reload_subscribers: Vec<std::sync::mpsc::Sender<plugin_reload::ReloadEvent>>,
+    shadow_cache: Option<plugin_shadow::ShadowCache>,
+    lazy: Option<plugin_lazy::LazyPlugins>,
+    /// Native dependencies opened for plugins, closed after their libraries.
+    preloaded: plugin_deps::PreloadedLibraries,
+    trusted_keys: Vec<plugin_signature::VerifyingKey>,
+    signature_policy: plugin_signature::SignaturePolicy,
+    #[cfg(unix)]
+    isolation: Option<plugin_isolation::IsolationConfig>,
+    #[cfg(feature = "wasm")]
+    wasm_limits: plugin_wasm::WasmLimits,
 }
 
 impl Default for PluginManager {
@@ -236,11 +356,33 @@ impl Default for PluginManager {
         Self::new()
     }
 }
+
+impl Drop for PluginManager {
+    /// Unloads every plugin, as `deregister_all_plugins` does, then closes
+    /// the remaining libraries in reverse load order, and finally the
+    /// dependencies preloaded for them.
+    fn drop(&mut self) {
+        self.absorb_lazy_plugins();
+        self.deregister_all_plugins();
+        self.plugin_libraries.clear();
+        let mut ids: Vec<LibraryId> = self.libraries.keys().copied().collect();
+        ids.sort_unstable_by(|a, b| b.cmp(a));
+        for id in ids {
+            if let Some(loaded) = self.libraries.remove(&id) {
+                loaded.close();
+            }
+        }
+        self.preloaded.close_all();
+        let _ = std::fs::remove_dir_all(&self.reload_dir);
+        if let Some(shadow_cache) = &self.shadow_cache {
+            shadow_cache.cleanup();
+        }
+    }
+}
 macro_rules! get_plugins_by_variant {
     ($self:expr, $variant:path, $trait_type:ty) => {
         $self
-            .plugins
-            .iter()
+            .registered_plugins()
             .filter_map(|(name, plugin)| match plugin {
                 $variant(inner) => Some((name, inner as $trait_type)),
                 _ => None,
@@ -253,19 +395,41 @@ impl PluginManager {
     pub fn new() -> Self {
         PluginManager {
             plugins: HashMap::new(),
+            config_source: plugin_config::ConfigSource::Env,
+            entry_configs: HashMap::new(),
+            entry_origins: HashMap::new(),
             plugin_path: Vec::new(),
-            libraries: Vec::new(),
+            libraries: HashMap::new(),
+            plugin_libraries: HashMap::new(),
+            registration_order: Vec::new(),
+            next_library_id: 0,
+            hot_reload: false,
+            reload_dir: private_temp_dir("reload"),
+            reload_subscribers: Vec::new(),
+            shadow_cache: None,
+            lazy: None,
+            preloaded: plugin_deps::PreloadedLibraries::default(),
+            trusted_keys: Vec::new(),
+            signature_policy: plugin_signature::SignaturePolicy::Off,
+            #[cfg(unix)]
+            isolation: None,
+            #[cfg(feature = "wasm")]
+            wasm_limits: plugin_wasm::WasmLimits::default(),
         }
     }
 
     pub fn activate_plugins(mut self) -> Result<PluginManager, Box<dyn std::error::Error>> {
-        let meta_data = self.get_plugin_metadata();
+        let (meta_data, origins) = self.config_source.load_with_origins()?;
+        self.entry_origins = origins;
         log::debug!("Plugin metadata: {:?}", meta_data);
         let mut registrations = Vec::new();
+        let plugin_index = meta_data.plugin_index;
+        let static_registrations = plugin_static::static_registrations();
         if let Some(plugin_config) = meta_data.plugins {
-            for (group_or_name, plugin_entry) in plugin_config {
-                registrations.push((group_or_name, plugin_entry));
-            }
+            registrations.extend(plugin_config);
+            registrations.sort_by(|(a, _), (b, _)| a.cmp(b));
+        } else if !static_registrations.is_empty() {
+            log::debug!("No plugin metadata found, using static plugins only");
         } else {
             log::error!("No plugin metadata found in manifest");
             return Err("No plugin metadata found in manifest".into());
@@ -277,15 +441,42 @@ impl PluginManager {
                 }
             }
         }
+        registrations.retain(|(group_or_name, plugin_entry)| {
+            if !plugin_entry.is_enabled() {
+                log::info!("Skipping disabled plugin entry: {group_or_name}");
+            }
+            plugin_entry.is_enabled()
+        });
+        registrations.sort_by_key(|(_, plugin_entry)| std::cmp::Reverse(plugin_entry.priority()));
         for (group_or_name, plugin_entry) in registrations {
-            self.activation_registration(group_or_name.clone(), &plugin_entry)?;
+            if let Some(config) = plugin_entry.spec().and_then(|spec| spec.config.clone()) {
+                self.entry_configs.insert(group_or_name.clone(), config);
+            }
+            if self.lazy.is_some() {
+                self.defer_entry(&group_or_name, &plugin_entry, plugin_index.as_ref())?;
+            } else {
+                self.activation_registration(group_or_name.clone(), &plugin_entry)?;
+            }
+        }
+        for registration in static_registrations {
+            self.register_static(registration.group_or_name.to_string(), registration.create)?;
         }
         Ok(self)
     }
 
-    /// Retrieves the environment variable CARGO_MANIFEST_PATH containing the
-    /// path to  manifest file. The file should contain the plugin metadata
-    /// in TOML format which contains the following structure:
+    /// The `config` table of the detailed entry `group_or_name`.
+    pub fn get_entry_config(&self, group_or_name: &str) -> Option<&serde_json::Value> {
+        self.entry_configs.get(group_or_name)
+    }
+
+    /// The configuration layer the entry `group_or_name` came from, for
+    /// managers created with `from_layers`.
+    pub fn get_entry_origin(&self, group_or_name: &str) -> Option<&plugin_layers::ConfigOrigin> {
+        self.entry_origins.get(group_or_name)
+    }
+
+    /// Reads the plugin metadata from the manager's configuration source, see
+    /// [`plugin_config`]. The metadata has the following structure:
     ///
     /// ```toml
     /// [package.metadata.plugins]
@@ -294,33 +485,14 @@ impl PluginManager {
     /// [package.metadata.plugins.inventory]
     /// inventory_plugin = "/path/to/inventory_plugin.so"
     /// ```
+    ///
+    /// Errors are logged and yield empty metadata; `activate_plugins` returns
+    /// them instead.
     pub fn get_plugin_metadata(&self) -> Metadata {
-        // FIXME: Rename the plugin_a_path variable
-        let plugin_a_path =
-            std::env::var("CARGO_MANIFEST_PATH").unwrap_or_else(|_| ".".to_string());
-
-        let file_string = std::fs::read_to_string(plugin_a_path);
-        let manifest = match file_string {
-            Ok(manifest) => manifest,
-            Err(msg) => {
-                eprintln!("Error reading manifest file {}", msg);
-                return Metadata { plugins: None };
-            }
-        };
-        let value: toml::Value = toml::from_str(&manifest).unwrap();
-        // let metadata = if let Some(meta_data) = value
-        if let Some(meta_data) = value
-            .get("package")
-            .and_then(|p| p.get("metadata"))
-            .and_then(|m| m.as_table())
-        {
-            let meta: Result<Metadata, toml::de::Error> =
-                toml::from_str(&toml::to_string(meta_data).unwrap());
-            meta.unwrap()
-        } else {
-            Metadata { plugins: None }
-        }
-        // metadata
+        self.config_source.load().unwrap_or_else(|error| {
+            log::error!("{error}");
+            Metadata::default()
+        })
     }
 
     fn activation_registration(
@@ -329,29 +501,129 @@ impl PluginManager {
         plugin_entry: &PluginEntry,
     ) -> Result<(), Box<dyn std::error::Error>> {
         match plugin_entry {
-            PluginEntry::Individual(path) => {
-                log::debug!("Loading individual plugin: {group_or_name} {path}");
-                let (library, plugins) = self.load_plugin(path)?;
-                self.libraries.push(library);
-                for plugin in plugins {
-                    self.register_plugin(plugin);
+            PluginEntry::Individual(path) => match plugin_discovery::glob_libraries(path)? {
+                Some(libraries) => self.register_libraries(&group_or_name, libraries)?,
+                None => {
+                    log::debug!("Loading individual plugin: {group_or_name} {path}");
+                    self.register_from_path(path)?;
                 }
+            },
+            PluginEntry::Detailed(spec) => {
+                log::debug!("Loading individual plugin: {group_or_name} {spec:?}");
+                self.register_entry(spec)?;
             }
             PluginEntry::Group(group_plugins) => {
-                group_plugins.iter().for_each(|(name, path)| {
+                for (name, path) in group_plugins {
                     log::debug!("Loading plugin group: {group_or_name}, {name} {path}");
-                    let (library, plugins) = self.load_plugin(path).unwrap();
-                    self.libraries.push(library);
-                    for plugin in plugins {
-                        self.register_plugin(plugin);
-                    }
-                });
+                    self.register_from_path(path)?;
+                }
+            }
+            PluginEntry::Directory(spec) => {
+                self.register_libraries(&group_or_name, spec.libraries()?)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Registers the libraries found for a directory or glob entry.
+    fn register_libraries(
+        &mut self,
+        group_or_name: &str,
+        libraries: Vec<PathBuf>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for library in libraries {
+            let path = library.to_string_lossy();
+            log::debug!("Loading plugin directory: {group_or_name}, {path}");
+            self.register_from_path(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Loads the library at `path` and registers its plugins.
+    fn register_from_path(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.register_entry(&PluginSpec::from_path(path))
+    }
+
+    /// Loads the library described by `spec` and registers its plugins.
+    fn register_entry(&mut self, spec: &PluginSpec) -> Result<(), Box<dyn std::error::Error>> {
+        if is_wasm_path(&spec.path) {
+            self.verify_library(&read_library(Path::new(&spec.path))?, spec)?;
+            if let Some(requirement) = &spec.version {
+                let path = Path::new(&spec.path);
+                return Err(plugin_abi::unknown_plugin_version(requirement, path).into());
             }
+            return self.register_wasm(&spec.path);
+        }
+        #[cfg(unix)]
+        if let Some(config) = &self.isolation {
+            let path = Path::new(&spec.path);
+            let bytes = read_library(path)?;
+            self.verify_library(&bytes, spec)?;
+            plugin_inspect::inspect_library(path, &bytes, spec)?;
+            for plugin in self.load_isolated(config, spec)? {
+                let name = plugin.try_name()?;
+                self.insert_plugin(name, plugin);
+            }
+            return Ok(());
+        }
+        let (library, plugins) = self.load_library(spec)?;
+        self.register_library_plugins(library, plugins)
+    }
+
+    #[cfg(feature = "wasm")]
+    fn register_wasm(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        for plugin in plugin_wasm::load_wasm_plugins(Path::new(path), self.wasm_limits)? {
+            let name = plugin.try_name()?;
+            self.insert_plugin(name, plugin);
         }
         Ok(())
     }
 
+    #[cfg(not(feature = "wasm"))]
+    fn register_wasm(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        Err(format!("Loading WASM plugin {path} requires the `wasm` feature").into())
+    }
+
+    /// Checks the pinned digest and the signature of the file about to be
+    /// opened for `spec`.
+    fn verify_library(
+        &self,
+        bytes: &[u8],
+        spec: &PluginSpec,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        verify_integrity(bytes, spec)?;
+        self.verify_signature(bytes, Path::new(&spec.path))
+    }
+
+    /// Loads `spec.path` through the shadow cache when it is enabled.
+    fn load_library(
+        &self,
+        spec: &PluginSpec,
+    ) -> Result<(LoadedLibrary, Vec<Plugins>), Box<dyn std::error::Error>> {
+        let path = PathBuf::from(&spec.path);
+        let (library, plugins) = match &self.shadow_cache {
+            Some(shadow_cache) if path.exists() => {
+                let copy = shadow_cache.stage(&path)?;
+                self.load_plugin_entry(&copy.to_string_lossy(), spec)?
+            }
+            _ => self.load_plugin_entry(&spec.path, spec)?,
+        };
+        Ok((
+            LoadedLibrary::with_spec(library, path, spec.clone()),
+            plugins,
+        ))
+    }
+
+    /// Loads `filename` and calls its `create_plugins` entry point.
     pub fn load_plugin(&self, filename: &str) -> PluginResultNew {
+        self.load_plugin_entry(filename, &PluginSpec::from_path(filename))
+    }
+
+    /// Loads `filename` and calls the entry point configured in `spec`.
+    ///
+    /// `filename` is the file actually opened, which differs from `spec.path`
+    /// when loading from a shadow or staged copy.
+    pub fn load_plugin_entry(&self, filename: &str, spec: &PluginSpec) -> PluginResultNew {
         let path = Path::new(filename);
 
         if !path.exists() {
@@ -362,25 +634,122 @@ impl PluginManager {
             log::debug!("Attempting to load plugin: {}", filename);
         }
 
-        let library = unsafe { Library::new(path)? };
+        // Read once for every check made before the library is opened.
+        let bytes = read_library(path)?;
+        self.verify_library(&bytes, spec)?;
+        plugin_inspect::inspect_library(path, &bytes, spec)?;
+        self.preload_dependencies(path, &bytes, spec)?;
+        let library = plugin_dlopen::open_library(path, &spec.flags)?;
         log::debug!("Library loaded successfully");
 
-        let create_plugin: Symbol<PluginCreateNew> = unsafe { library.get(b"create_plugins")? };
-        log::debug!("Found create_plugins symbol");
+        if spec.is_default_entry_point() && plugin_ffi::has_ffi_descriptor(&library) {
+            log::debug!("Found C ABI plugin descriptor");
+            if let Some(requirement) = &spec.version {
+                return Err(plugin_abi::unknown_plugin_version(requirement, path).into());
+            }
+            let plugins = plugin_ffi::create_ffi_plugins(&library, path)?;
+            log::debug!("Plugin created successfully");
+            return Ok((library, plugins));
+        }
+
+        let descriptor = plugin_abi::check_library_abi(&library, path)?;
+        if let Some(requirement) = &spec.version {
+            plugin_abi::check_plugin_version(descriptor, requirement, path)?;
+        }
 
-        let plugins = unsafe { create_plugin() };
+        let entry_point = spec.entry_point();
+        let plugins = match spec.mode {
+            EntryMode::Multiple => {
+                let create_plugins: Symbol<PluginCreateNew> =
+                    unsafe { library.get(entry_point.as_bytes())? };
+                log::debug!("Found {entry_point} symbol");
+                plugin_errors::catch_panic(entry_point, || unsafe { create_plugins() })
+            }
+            EntryMode::Single => {
+                let create_plugin: Symbol<PluginFactory> =
+                    unsafe { library.get(entry_point.as_bytes())? };
+                log::debug!("Found {entry_point} symbol");
+                plugin_errors::catch_panic(entry_point, || {
+                    vec![Plugins::Base(unsafe { create_plugin() })]
+                })
+            }
+        }
+        .map_err(|error| plugin_errors::with_library(error.into(), Some(path)))?;
         log::debug!("Plugin created successfully");
 
         Ok((library, plugins))
     }
 
-    pub fn register_plugin(&mut self, plugin: Plugins) {
-        let name = plugin.name();
+    /// Registers the plugins created from `library`, which stays open until the
+    /// last of them is deregistered.
+    fn register_library_plugins(
+        &mut self,
+        library: LoadedLibrary,
+        plugins: Vec<Plugins>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let named = match name_plugins(plugins, &library.path) {
+            Ok(named) => named,
+            Err(error) => {
+                library.close();
+                return Err(error);
+            }
+        };
+        let id = self.insert_library(library);
+        for (name, plugin) in named {
+            self.insert_plugin(name.clone(), plugin);
+            self.plugin_libraries.insert(name, id);
+            if let Some(loaded) = self.libraries.get_mut(&id) {
+                loaded.refcount += 1;
+            }
+        }
+        self.release_library(id);
+        Ok(())
+    }
+
+    fn insert_library(&mut self, loaded: LoadedLibrary) -> LibraryId {
+        let id = self.next_library_id;
+        self.next_library_id += 1;
+        self.libraries.insert(id, loaded);
+        id
+    }
+
+    /// Closes the library if no registered plugin was created from it.
+    ///
+    /// Callers must drop the plugins first: their vtables live in the library.
+    fn release_library(&mut self, id: LibraryId) {
+        if let Some(loaded) = self.libraries.get(&id)
+            && loaded.refcount == 0
+            && let Some(loaded) = self.libraries.remove(&id)
+        {
+            loaded.close();
+        }
+    }
+
+    /// Decrements the refcount of the library that created `name`.
+    fn release_plugin_library(&mut self, name: &str) {
+        if let Some(id) = self.plugin_libraries.remove(name) {
+            if let Some(loaded) = self.libraries.get_mut(&id) {
+                loaded.refcount = loaded.refcount.saturating_sub(1);
+            }
+            self.release_library(id);
+        }
+    }
+
+    /// Registers a plugin created by the host. A panic in its `name` is
+    /// returned as `PluginError::PluginPanicked`.
+    pub fn register_plugin(&mut self, plugin: Plugins) -> Result<(), plugin_errors::PluginError> {
+        let name = plugin.try_name()?;
+        self.insert_plugin(name, plugin);
+        Ok(())
+    }
+
+    fn insert_plugin(&mut self, name: PluginName, plugin: Plugins) {
         log::info!("Registering plugin: {:?}", name);
 
         println!("Registering plugin: {}", name);
         if let hash_map::Entry::Vacant(entry) = self.plugins.entry(name.clone()) {
             entry.insert(plugin);
+            self.registration_order.push(name);
         } else {
             let msg = format!("Plugin '{}' already registered", &name);
             log::error!("{msg}");
@@ -389,14 +758,30 @@ impl PluginManager {
     }
 
     /// Gets a plugin as a trait object based on its type
+    ///
+    /// In lazy mode this opens the library providing the plugin if needed.
     pub fn get_plugin(&self, name: &str) -> Option<&Plugins> {
-        self.plugins.get(name)
+        self.find_plugin(name).ok()
+    }
+
+    fn find_plugin(&self, name: &str) -> Result<&Plugins, Box<dyn std::error::Error>> {
+        if let Some(plugin) = self.plugins.get(name) {
+            return Ok(plugin);
+        }
+        match self.lazy.as_ref().and_then(|lazy| lazy.get(self, name)) {
+            Some(result) => Ok(result?),
+            None => {
+                let msg = format!("Plugin '{}' not found", name);
+                log::error!("{msg}");
+                Err(msg.into())
+            }
+        }
     }
 
     /// Gets an inventory plugin, returns None if the plugin is not a Base variant
     #[allow(clippy::borrowed_box)]
     pub fn get_base_plugin(&self, name: &str) -> Option<&Box<dyn Plugin>> {
-        self.plugins.get(name).and_then(|plugin| match plugin {
+        self.get_plugin(name).and_then(|plugin| match plugin {
             Plugins::Base(base) => Some(base),
             _ => None,
         })
@@ -405,7 +790,7 @@ impl PluginManager {
     #[allow(clippy::borrowed_box)]
     /// Gets an inventory plugin, returns None if the plugin is not an Inventory variant
     pub fn get_inventory_plugin(&self, name: &str) -> Option<&Box<dyn PluginInventory>> {
-        self.plugins.get(name).and_then(|plugin| match plugin {
+        self.get_plugin(name).and_then(|plugin| match plugin {
             Plugins::Inventory(inventory) => Some(inventory),
             _ => None,
         })
@@ -435,47 +820,142 @@ impl PluginManager {
     //     res
     // }
 
+    /// Registered plugins, including those of libraries opened lazily.
+    fn registered_plugins(&self) -> impl Iterator<Item = (&PluginName, &Plugins)> {
+        let lazy = self.lazy.iter().flat_map(|lazy| lazy.loaded_plugins());
+        self.plugins.iter().chain(lazy)
+    }
+
     /// Gets all Base plugins with their trait objects
+    ///
+    /// In lazy mode this only covers libraries that have been opened, since
+    /// the type of a plugin is unknown until then; call
+    /// [`PluginManager::load_lazy_plugins`] first to include every plugin.
     #[allow(clippy::borrowed_box)]
     pub fn get_plugins_by_type_base(&self) -> Vec<(&String, &Box<dyn Plugin>)> {
         get_plugins_by_variant!(self, Plugins::Base, &Box<dyn Plugin>)
     }
 
     /// Gets all Inventory plugins with their trait objects
+    ///
+    /// Like [`PluginManager::get_plugins_by_type_base`], this only covers
+    /// libraries that have been opened in lazy mode.
     #[allow(clippy::borrowed_box)]
     pub fn get_plugins_by_type_inventory(&self) -> Vec<(&String, &Box<dyn PluginInventory>)> {
         get_plugins_by_variant!(self, Plugins::Inventory, &Box<dyn PluginInventory>)
     }
 
-    /// Deregisters the plugin with the given name.
+    /// Deregisters the plugin with the given name, closing its library when no
+    /// other registered plugin was created from it.
+    ///
+    /// In lazy mode an indexed plugin whose library has not been opened yet is
+    /// removed from the index without opening the library; unindexed libraries
+    /// are still opened to find the plugin.
     pub fn deregister_plugin(&mut self, name: &str) -> Option<String> {
-        if let Some(plugin) = self.plugins.remove(name) {
-            log::info!("De-registering plugin: {}", name);
-            Some(plugin.name())
-        } else {
-            None
+        if let Some(lazy) = &mut self.lazy {
+            if lazy.deregister(name) {
+                log::info!("De-registering plugin: {}", name);
+                return Some(name.to_string());
+            }
+            if self.get_plugin(name).is_some() {
+                self.absorb_lazy_plugins();
+            }
         }
+        let plugin = self.plugins.remove(name)?;
+        log::info!("De-registering plugin: {}", name);
+        let plugin_name = plugin.try_name().unwrap_or_else(|_| name.to_string());
+        self.unload_plugin(name, plugin);
+        Some(plugin_name)
     }
 
-    /// Deregisters all plugins.
+    /// Deregisters all plugins, in reverse registration order, and closes
+    /// their libraries.
     pub fn deregister_all_plugins(&mut self) -> Vec<String> {
+        self.absorb_lazy_plugins();
+        if let Some(lazy) = &mut self.lazy {
+            lazy.clear();
+        }
+        // Plugins inserted into `plugins` directly have no recorded order and go first.
+        let mut names: Vec<PluginName> = self
+            .plugins
+            .keys()
+            .filter(|name| !self.registration_order.contains(name))
+            .cloned()
+            .collect();
+        names.sort();
+        names.extend(self.registration_order.iter().rev().cloned());
+
         let mut deregistered_plugins = Vec::new();
-        for (name, plugin) in self.plugins.drain() {
-            log::info!("De-registering plugin: {}", name);
-            deregistered_plugins.push(plugin.name());
+        for name in names {
+            if let Some(plugin) = self.plugins.remove(&name) {
+                log::info!("De-registering plugin: {}", name);
+                deregistered_plugins.push(plugin.try_name().unwrap_or_else(|_| name.clone()));
+                self.unload_plugin(&name, plugin);
+            }
         }
         deregistered_plugins
     }
 
+    /// Tears down a plugin removed from `plugins`: `Plugin::on_unload` runs
+    /// first, then the plugin is passed to its library's `destroy_plugins`
+    /// export (or dropped when there is none), and finally the library is
+    /// closed if no other plugin was created from it.
+    fn unload_plugin(&mut self, name: &str, plugin: Plugins) {
+        self.registration_order
+            .retain(|registered| registered != name);
+        if let Err(error) = plugin.on_unload() {
+            log::error!("{error}");
+        }
+        let destroy = self
+            .plugin_libraries
+            .get(name)
+            .and_then(|id| self.libraries.get(id))
+            .and_then(|loaded| loaded.destroy);
+        plugin_structs::destroy_plugins(destroy, vec![plugin]);
+        self.release_plugin_library(name);
+    }
+
+    /// Gets the path of the library the plugin was loaded from.
+    pub fn get_plugin_library(&self, name: &str) -> Option<&Path> {
+        self.plugin_libraries
+            .get(name)
+            .and_then(|id| self.libraries.get(id))
+            .map(|loaded| loaded.path.as_path())
+            .or_else(|| self.lazy.as_ref()?.library_of(name))
+    }
+
+    /// Gets the paths of all libraries that are currently open.
+    pub fn get_loaded_libraries(&self) -> Vec<&Path> {
+        let mut libraries: Vec<&Path> = self
+            .libraries
+            .values()
+            .map(|loaded| loaded.path.as_path())
+            .collect();
+        if let Some(lazy) = &self.lazy {
+            libraries.extend(lazy.loaded_libraries());
+        }
+        libraries
+    }
+
     /// Gets all the **names** of the registered plugins.
+    ///
+    /// In lazy mode this includes the indexed plugins of libraries that have
+    /// not been opened yet.
     pub fn get_all_plugin_names(&self) -> Vec<&String> {
-        self.plugins.keys().collect()
+        let mut names: Vec<&String> = self.plugins.keys().collect();
+        if let Some(lazy) = &self.lazy {
+            names.extend(lazy.names());
+        }
+        names
     }
 
     /// Gets all the **names** and **groups** of the registered plugins.
+    ///
+    /// Unlike [`PluginManager::get_all_plugin_names`], this only covers
+    /// libraries that have been opened in lazy mode, since the group of a
+    /// plugin is unknown until then.
     pub fn get_all_plugin_names_and_groups(&self) -> Vec<(String, String)> {
-        self.plugins
-            .iter()
+        self.registered_plugins()
             .map(|(name, plugin)| (name.clone(), plugin.group_name()))
             .collect()
     }
@@ -485,12 +965,25 @@ impl PluginManager {
         name: &str,
         context: &dyn Any,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        if let Some(plugin) = self.plugins.get(name) {
-            plugin.execute(context)
-        } else {
-            let msg = format!("Plugin '{}' not found", name);
-            log::error!("{msg}");
-            Err(msg.into())
+        self.find_plugin(name)?
+            .execute(context)
+            .map_err(|error| plugin_errors::with_library(error, self.get_plugin_library(name)))
+    }
+
+    /// Calls `PluginInventory::load` on the named inventory plugin.
+    pub fn load_inventory_plugin(&self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        match self.get_plugin(name) {
+            Some(plugin @ Plugins::Inventory(_)) => plugin.load().map_err(|error| {
+                plugin_errors::with_library(error.into(), self.get_plugin_library(name))
+            }),
+            Some(Plugins::Base(_)) => {
+                Err(format!("Plugin '{}' is not an inventory plugin", name).into())
+            }
+            None => {
+                let msg = format!("Plugin '{}' not found", name);
+                log::error!("{msg}");
+                Err(msg.into())
+            }
         }
     }
     pub fn with_path(mut self, path: &str, group: Option<&str>) -> Result<Self, Error> {
@@ -525,41 +1018,92 @@ impl PluginManager {
     }
 }
 
+/// Returns true when `path` names a WASM module rather than a native library.
+fn is_wasm_path(path: &str) -> bool {
+    matches!(
+        Path::new(path).extension().and_then(|e| e.to_str()),
+        Some("wasm") | Some("wat")
+    )
+}
+
+/// A temp directory name unique to one caller in this process.
+fn private_temp_dir(kind: &str) -> PathBuf {
+    static NEXT: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let instance = NEXT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    std::env::temp_dir().join(format!(
+        "plugin_manager-{kind}-{}-{instance}",
+        std::process::id()
+    ))
+}
+
+/// Reads a library for the checks made before it is opened.
+fn read_library(path: &Path) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    std::fs::read(path)
+        .map_err(|error| format!("Unable to read plugin {}: {error}", path.display()).into())
+}
+
+/// Checks the library's bytes against the digest pinned in `spec`, if any.
+///
+/// The bytes may come from a shadow copy; errors report the configured path.
+fn verify_integrity(bytes: &[u8], spec: &PluginSpec) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(expected) = &spec.sha256 else {
+        return Ok(());
+    };
+    let actual = plugin_shadow::sha256_bytes(bytes);
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(plugin_errors::PluginError::IntegrityMismatch {
+            path: PathBuf::from(&spec.path),
+            expected: expected.to_ascii_lowercase(),
+            actual,
+        }
+        .into());
+    }
+    log::debug!("Verified sha256 of {}", spec.path);
+    Ok(())
+}
+
+/// Pairs every plugin with its name, catching panics raised by `Plugin::name`.
+fn name_plugins(
+    plugins: Vec<Plugins>,
+    path: &Path,
+) -> Result<Vec<(PluginName, Plugins)>, Box<dyn std::error::Error>> {
+    let mut named = Vec::with_capacity(plugins.len());
+    let mut failure = None;
+    for plugin in plugins {
+        match plugin.try_name() {
+            Ok(name) => named.push((name, plugin)),
+            Err(error) => {
+                failure.get_or_insert(error);
+            }
+        }
+    }
+    match failure {
+        Some(error) => Err(plugin_errors::with_library(error.into(), Some(path))),
+        None => Ok(named),
+    }
+}
+
+#[cfg(test)]
+#[path = "../tests/common/mod.rs"]
+mod test_common;
+
 #[cfg(test)]
 mod tests {
+    use libloading::Library;
     use std::path::PathBuf;
 
     use super::*;
+    use crate::test_common::{TestDir, make_file_path, manifest_path};
 
-    fn set_env_var() {
-        let file_name = match std::env::consts::OS {
-            "linux" => "Cargo.toml",
-            "windows" => "Cargo-windows.toml",
-            "macos" => "Cargo-macos.toml",
-            _ => "Cargo.toml",
-        };
-        let file = format!("../tests/plugin_mods/{}", file_name);
-        unsafe {
-            std::env::set_var("CARGO_MANIFEST_PATH", file);
-        }
-    }
-
-    fn make_file_path(module_name: &str) -> String {
-        let mut path_name = PathBuf::new();
-        let mut module_name_prefix = String::from(std::env::consts::DLL_PREFIX);
-        module_name_prefix.push_str(module_name);
-        path_name.push("..");
-        path_name.push("target");
-        path_name.push("release");
-        path_name.push(module_name_prefix);
-        path_name.set_extension(std::env::consts::DLL_EXTENSION);
-        path_name.to_string_lossy().to_string()
+    /// The absolute path of a plugin library, as resolved from a config file.
+    fn resolved_file_path(module_name: &str) -> String {
+        let path = std::fs::canonicalize(make_file_path(module_name)).unwrap();
+        path.to_string_lossy().to_string()
     }
 
     #[test]
     fn get_plugin_path_test() {
-        set_env_var();
-        let plugin_manager = PluginManager::new();
+        let plugin_manager = PluginManager::from_manifest(manifest_path());
         let metadata = plugin_manager.get_plugin_metadata();
         let plugins = metadata.plugins;
         match plugins {
@@ -567,11 +1111,17 @@ mod tests {
                 for (group, entry) in plug_entry {
                     match entry {
                         PluginEntry::Individual(path) => {
-                            assert_eq!(path, make_file_path("plugin_mods"));
+                            assert_eq!(path, resolved_file_path("plugin_mods"));
+                        }
+                        PluginEntry::Detailed(spec) => {
+                            panic!("Unexpected detailed entry: {spec:?}");
+                        }
+                        PluginEntry::Directory(spec) => {
+                            panic!("Unexpected directory entry: {spec:?}");
                         }
                         PluginEntry::Group(path) => {
                             path.iter().for_each(|(metadata_name, path)| {
-                                assert_eq!(path, &make_file_path("plugin_inventory"));
+                                assert_eq!(path, &resolved_file_path("plugin_inventory"));
                                 assert_eq!(metadata_name, "inventory_a");
                                 assert_eq!(group, "inventory");
                             });
@@ -587,8 +1137,7 @@ mod tests {
 
     #[test]
     fn get_plugin_metadata_test() {
-        set_env_var();
-        let plugin_manager = PluginManager::new();
+        let plugin_manager = PluginManager::from_manifest(manifest_path());
         let metadata = plugin_manager.get_plugin_metadata();
         assert!(metadata.plugins.is_some());
         // Check if the metadata contains the expected number of plugin paths.
@@ -597,8 +1146,7 @@ mod tests {
 
     #[test]
     fn activate_plugins_test() {
-        set_env_var();
-        let mut plugin_manager = PluginManager::new();
+        let mut plugin_manager = PluginManager::from_manifest(manifest_path());
         plugin_manager = plugin_manager.activate_plugins().unwrap();
         assert!(plugin_manager.get_plugin("plugin_a").is_some());
         assert_eq!(plugin_manager.plugins.len(), 3);
@@ -608,8 +1156,7 @@ mod tests {
     #[should_panic]
     /// Test for duplicate activation of plugins.
     fn activate_plugins_and_panic_test() {
-        set_env_var();
-        let mut plugin_manager = PluginManager::new();
+        let mut plugin_manager = PluginManager::from_manifest(manifest_path());
         plugin_manager = plugin_manager.activate_plugins().unwrap();
         _ = plugin_manager.activate_plugins().unwrap();
     }
@@ -636,8 +1183,9 @@ mod tests {
 
     #[test]
     fn activate_plugins_with_groups_test() {
-        set_env_var();
-        let plugin_manager = PluginManager::new().activate_plugins().unwrap();
+        let plugin_manager = PluginManager::from_manifest(manifest_path())
+            .activate_plugins()
+            .unwrap();
 
         // Get all plugins in the "base" group
         let inventory_plugins = plugin_manager.get_plugins_by_type_base();
@@ -649,12 +1197,24 @@ mod tests {
         assert_eq!(inventory_plugins[0].1.name(), "inventory_a");
 
         assert_eq!(plugin_manager.plugins.len(), 3);
+
+        // A group member that fails to load is reported, not a panic.
+        let inventory = make_file_path("plugin_inventory");
+        let error = PluginManager::from_str(&format!(
+            "[package.metadata.plugins.inventory]\ninventory_a = \"{inventory}\"\n\
+             missing = \"../target/release/missing.so\"\n"
+        ))
+        .activate_plugins()
+        .err()
+        .unwrap();
+        assert!(error.to_string().contains("missing.so"), "{error}");
     }
 
     #[test]
     fn get_all_plugin_names_and_groups_test() {
-        set_env_var();
-        let plugin_manager = PluginManager::new().activate_plugins().unwrap();
+        let plugin_manager = PluginManager::from_manifest(manifest_path())
+            .activate_plugins()
+            .unwrap();
         let all_plugins = plugin_manager.get_all_plugin_names_and_groups();
         assert_eq!(all_plugins.len(), 3);
         all_plugins
@@ -669,8 +1229,9 @@ mod tests {
 
     #[test]
     fn deregister_plugin_test() {
-        set_env_var();
-        let mut plugin_manager = PluginManager::new().activate_plugins().unwrap();
+        let mut plugin_manager = PluginManager::from_manifest(manifest_path())
+            .activate_plugins()
+            .unwrap();
         assert_eq!(plugin_manager.plugins.len(), 3);
 
         // Deregister individual plugin
@@ -694,8 +1255,9 @@ mod tests {
 
     #[test]
     fn deregister_all_plugins_test() {
-        set_env_var();
-        let mut plugin_manager = PluginManager::new().activate_plugins().unwrap();
+        let mut plugin_manager = PluginManager::from_manifest(manifest_path())
+            .activate_plugins()
+            .unwrap();
         assert_eq!(plugin_manager.plugins.len(), 3);
 
         // Deregister all plugins
@@ -706,8 +1268,7 @@ mod tests {
 
     #[test]
     fn plugin_manager_new_test() {
-        set_env_var();
-        let mut plugin_manager = PluginManager::new();
+        let mut plugin_manager = PluginManager::from_manifest(manifest_path());
         assert_eq!(plugin_manager.plugins.len(), 0);
         plugin_manager = plugin_manager.activate_plugins().unwrap();
         assert_eq!(plugin_manager.plugins.len(), 3);
@@ -715,8 +1276,9 @@ mod tests {
 
     #[test]
     fn execute_plugin_test() {
-        set_env_var();
-        let plugin_manager = PluginManager::new().activate_plugins().unwrap();
+        let plugin_manager = PluginManager::from_manifest(manifest_path())
+            .activate_plugins()
+            .unwrap();
         let plugin_name = "plugin_a";
         if let Some(plugin) = plugin_manager.get_plugin(plugin_name) {
             let execution = plugin.execute(&());
@@ -728,8 +1290,9 @@ mod tests {
 
     #[test]
     fn get_plugins_by_type_test() {
-        set_env_var();
-        let plugin_manager = PluginManager::new().activate_plugins().unwrap();
+        let plugin_manager = PluginManager::from_manifest(manifest_path())
+            .activate_plugins()
+            .unwrap();
         let base_plugins = plugin_manager.get_plugins_by_type_base();
         assert_eq!(base_plugins.len(), 2);
 
@@ -752,13 +1315,1640 @@ mod tests {
 
     #[test]
     fn with_path_test() {
-        set_env_var();
         let path = make_file_path("plugin_tasks");
-        let plugin_manager = PluginManager::new()
+        let plugin_manager = PluginManager::from_manifest(manifest_path())
             .with_path(&path, None)
             .unwrap()
             .activate_plugins()
             .unwrap();
         assert_eq!(plugin_manager.plugins.len(), 4);
     }
+
+    #[test]
+    fn abi_descriptor_mismatch_test() {
+        let path = Path::new("libplugin_mods.so");
+        let descriptor = plugin_abi::AbiDescriptor::new(c"0.1.0");
+        assert!(descriptor.validate(path).is_ok());
+
+        let mut descriptor = plugin_abi::AbiDescriptor::new(c"0.1.0");
+        descriptor.layout_version += 1;
+        match descriptor.validate(path) {
+            Err(plugin_errors::PluginError::AbiMismatch { field, .. }) => {
+                assert_eq!(field, "Plugins layout version")
+            }
+            other => panic!("Expected an ABI mismatch, got {:?}", other),
+        }
+
+        let mut descriptor = plugin_abi::AbiDescriptor::new(c"0.1.0");
+        descriptor.rustc_version = c"rustc 1.0.0".as_ptr();
+        let error = descriptor.validate(path).unwrap_err();
+        assert!(error.to_string().contains("rustc version"));
+    }
+
+    #[test]
+    fn load_ffi_plugin_test() {
+        let plugin_manager = PluginManager::new();
+        let filename = make_file_path("plugin_tasks");
+        let (_library, plugins) = plugin_manager.load_plugin(&filename).unwrap();
+        assert_eq!(plugins.len(), 1);
+        assert_eq!(plugins[0].name(), "task_a");
+        assert!(plugins[0].execute(&String::from("context")).is_ok());
+
+        // Arbitrary Rust types cannot cross the C ABI.
+        let error = plugins[0].execute(&42_u32).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<plugin_errors::PluginError>(),
+            Some(plugin_errors::PluginError::UnsupportedContext { .. })
+        ));
+    }
+
+    struct PanickingPlugin;
+
+    impl Plugin for PanickingPlugin {
+        fn name(&self) -> String {
+            String::from("panicking")
+        }
+
+        fn execute(&self, _context: &dyn Any) -> Result<(), Box<dyn std::error::Error>> {
+            panic!("execute exploded")
+        }
+    }
+
+    impl PluginInventory for PanickingPlugin {
+        fn load(&self) {
+            panic!("load exploded")
+        }
+    }
+
+    #[test]
+    fn ffi_invalid_value_test() {
+        use plugin_ffi::{FfiPluginHandle, FfiPluginKind, FfiStatus};
+
+        fn create() -> Vec<Plugins> {
+            vec![Plugins::Base(Box::new(PanickingPlugin))]
+        }
+        let list = plugin_ffi::export_plugins(create);
+        let mut raw = unsafe { std::ptr::read(list.ptr) };
+        unsafe { (list.free)(list.ptr, 0, list.capacity) };
+
+        // An undeclared kind is an error, not undefined behavior.
+        raw.kind = 7;
+        let error = unsafe { FfiPluginHandle::new(raw) }.err().unwrap();
+        assert!(matches!(
+            error.downcast_ref::<plugin_errors::PluginError>(),
+            Some(plugin_errors::PluginError::InvalidFfiValue {
+                field: "plugin kind",
+                value: 7
+            })
+        ));
+        assert!(FfiStatus::try_from(3).is_err());
+        assert_eq!(FfiStatus::try_from(2).unwrap(), FfiStatus::Panic);
+        assert_eq!(
+            FfiPluginKind::try_from(plugin_ffi::FFI_PLUGIN_INVENTORY).unwrap(),
+            FfiPluginKind::Inventory
+        );
+    }
+
+    #[test]
+    fn deregister_plugin_closes_library_test() {
+        let mut plugin_manager = PluginManager::from_manifest(manifest_path())
+            .activate_plugins()
+            .unwrap();
+        assert_eq!(plugin_manager.get_loaded_libraries().len(), 2);
+        let mods_library = plugin_manager.get_plugin_library("plugin_a").unwrap();
+        assert_eq!(mods_library, Path::new(&resolved_file_path("plugin_mods")));
+
+        // plugin_b still holds the library open.
+        plugin_manager.deregister_plugin("plugin_a");
+        assert_eq!(plugin_manager.get_loaded_libraries().len(), 2);
+        assert!(plugin_manager.get_plugin_library("plugin_a").is_none());
+
+        plugin_manager.deregister_plugin("plugin_b");
+        assert_eq!(plugin_manager.get_loaded_libraries().len(), 1);
+
+        plugin_manager.deregister_all_plugins();
+        assert!(plugin_manager.get_loaded_libraries().is_empty());
+    }
+
+    #[test]
+    fn hot_reload_test() {
+        let directory = TestDir::new("hot-reload");
+        let source = make_file_path("plugin_mods");
+        let library = directory.join(Path::new(&source).file_name().unwrap());
+        std::fs::copy(&source, &library).unwrap();
+
+        let mut plugin_manager = PluginManager::new().with_hot_reload();
+        let events = plugin_manager.subscribe_reload_events();
+        let entry = PluginEntry::Individual(library.to_string_lossy().to_string());
+        plugin_manager
+            .activation_registration("base".to_string(), &entry)
+            .unwrap();
+        assert!(plugin_manager.reload_changed().is_empty());
+
+        // Replace the library the way cargo does: write a new file, then move it into place.
+        let next = directory.join("next");
+        std::fs::copy(make_file_path("plugin_tasks"), &next).unwrap();
+        std::fs::rename(&next, &library).unwrap();
+
+        let expected = plugin_reload::ReloadEvent::Reloaded {
+            path: library.clone(),
+            added: vec![String::from("task_a")],
+            removed: vec![String::from("plugin_a"), String::from("plugin_b")],
+            replaced: Vec::new(),
+        };
+        assert_eq!(plugin_manager.reload_changed(), vec![expected.clone()]);
+        assert_eq!(events.try_recv().unwrap(), expected);
+        assert!(plugin_manager.get_plugin("task_a").is_some());
+        assert!(plugin_manager.get_plugin("plugin_a").is_none());
+        assert_eq!(
+            plugin_manager.get_loaded_libraries(),
+            vec![library.as_path()]
+        );
+        assert!(plugin_manager.reload_changed().is_empty());
+
+        // Each manager stages copies in its own directory, removed on drop.
+        let reload_dir = plugin_manager.reload_dir.clone();
+        assert!(reload_dir.is_dir());
+        assert_ne!(reload_dir, PluginManager::new().reload_dir);
+        drop(plugin_manager);
+        assert!(!reload_dir.exists());
+    }
+
+    #[test]
+    fn shadow_copy_test() {
+        let directory = TestDir::new("shadow");
+        let plugin_manager = PluginManager::from_manifest(manifest_path())
+            .with_shadow_copy_dir(directory.to_path_buf())
+            .activate_plugins()
+            .unwrap();
+        assert_eq!(plugin_manager.plugins.len(), 3);
+
+        // Plugins report the configured path, while the copies are what got opened.
+        let library = plugin_manager.get_plugin_library("plugin_a").unwrap();
+        assert_eq!(library, Path::new(&resolved_file_path("plugin_mods")));
+        let digest = plugin_shadow::sha256_file(library).unwrap();
+        let copies: Vec<PathBuf> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(copies.len(), 2);
+        assert!(copies.iter().any(|copy| {
+            copy.file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with(&digest)
+        }));
+
+        // Only the copies the manager made are removed.
+        let unrelated = directory.join("unrelated.txt");
+        std::fs::write(&unrelated, "not a copy").unwrap();
+        drop(plugin_manager);
+        let remaining: Vec<PathBuf> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(remaining, [unrelated]);
+
+        // Private caches do not share a directory.
+        assert_ne!(
+            plugin_shadow::ShadowCache::private().directory(),
+            plugin_shadow::ShadowCache::private().directory()
+        );
+    }
+
+    #[test]
+    fn execute_plugin_panic_test() {
+        let mut plugin_manager = PluginManager::new();
+        plugin_manager
+            .register_plugin(Plugins::Inventory(Box::new(PanickingPlugin)))
+            .unwrap();
+
+        let error = plugin_manager.execute_plugin("panicking", &()).unwrap_err();
+        match error.downcast_ref::<plugin_errors::PluginError>() {
+            Some(plugin_errors::PluginError::PluginPanicked {
+                plugin,
+                library,
+                message,
+            }) => {
+                assert_eq!(plugin, "panicking");
+                assert!(library.is_none());
+                assert_eq!(message, "execute exploded");
+            }
+            other => panic!("Expected a panic error, got {:?}", other),
+        }
+
+        let error = plugin_manager
+            .load_inventory_plugin("panicking")
+            .unwrap_err();
+        assert!(error.to_string().contains("load exploded"));
+    }
+
+    struct UnnamedPlugin;
+
+    impl Plugin for UnnamedPlugin {
+        fn name(&self) -> String {
+            panic!("name exploded")
+        }
+
+        fn execute(&self, _context: &dyn Any) -> Result<(), Box<dyn std::error::Error>> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn register_plugin_name_panic_test() {
+        let mut plugin_manager = PluginManager::new();
+        let error = plugin_manager
+            .register_plugin(Plugins::Base(Box::new(UnnamedPlugin)))
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            plugin_errors::PluginError::PluginPanicked { .. }
+        ));
+        assert!(error.to_string().contains("name exploded"));
+        assert!(plugin_manager.plugins.is_empty());
+
+        let plugin: Box<dyn Plugin> = Box::new(UnnamedPlugin);
+        assert_eq!(format!("{plugin:?}"), "BasePlugin { name: <panicked> }");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[ignore = "entry point of the helper process started by isolated_plugin_test"]
+    fn isolated_plugin_host() {
+        plugin_isolation::serve_if_requested();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn isolated_plugin_test() {
+        // The test binary itself acts as the helper process.
+        let args = ["tests::isolated_plugin_host", "--exact", "--ignored"];
+        let config = plugin_isolation::IsolationConfig::with_program(
+            std::env::current_exe().unwrap(),
+            args.iter().map(|arg| arg.to_string()).collect(),
+        )
+        .restart_on_crash(true);
+        let mut plugin_manager = PluginManager::new().with_isolation(config);
+        let entry = PluginEntry::Individual(make_file_path("plugin_mods"));
+        plugin_manager
+            .activation_registration("base".to_string(), &entry)
+            .unwrap();
+        assert_eq!(plugin_manager.plugins.len(), 2);
+        plugin_manager.execute_plugin("plugin_a", &()).unwrap();
+
+        // Kill the helper as if the plugin had crashed it.
+        let plugin = plugin_manager.get_base_plugin("plugin_a").unwrap();
+        let isolated = (plugin.as_ref() as &dyn Any)
+            .downcast_ref::<plugin_isolation::IsolatedPlugin>()
+            .unwrap();
+        let host_id = isolated.host_id();
+        std::process::Command::new("kill")
+            .args(["-9", &host_id.to_string()])
+            .status()
+            .unwrap();
+
+        let error = plugin_manager.execute_plugin("plugin_a", &()).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<plugin_errors::PluginError>(),
+            Some(plugin_errors::PluginError::HostCrashed { .. })
+        ));
+
+        // The helper was restarted and serves both plugins of the library again.
+        assert_ne!(isolated.host_id(), host_id);
+        plugin_manager.execute_plugin("plugin_b", &()).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn isolated_plugin_timeout_test() {
+        // A helper that never serves requests is given up on after the timeout.
+        let config =
+            plugin_isolation::IsolationConfig::with_program("sleep", vec!["30".to_string()])
+                .with_timeout(std::time::Duration::from_millis(200));
+        let mut plugin_manager = PluginManager::new().with_isolation(config);
+        let entry = PluginEntry::Individual(make_file_path("plugin_mods"));
+        let started = std::time::Instant::now();
+        let error = plugin_manager
+            .activation_registration("base".to_string(), &entry)
+            .unwrap_err();
+        assert!(started.elapsed() < std::time::Duration::from_secs(10));
+        assert!(error.to_string().contains("no response within"), "{error}");
+    }
+
+    /// A guest implementing the WASM plugin ABI by hand. The first byte of
+    /// the context selects the behaviour: `l` spins forever, `g` grows the
+    /// memory by 100 pages, `f` returns an error and anything else succeeds.
+    #[cfg(feature = "wasm")]
+    const WASM_PLUGIN: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global $next (mut i32) (i32.const 1024))
+          (data (i32.const 0) "wasm_plugin")
+          (data (i32.const 16) "bad context")
+          (func (export "plugin_alloc") (param $len i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $next))
+            (global.set $next (i32.add (global.get $next) (local.get $len)))
+            (local.get $ptr))
+          (func (export "plugin_dealloc") (param i32 i32))
+          (func (export "plugin_name") (result i64) (i64.const 11))
+          (func (export "plugin_execute") (param $ptr i32) (param $len i32) (result i64)
+            (local $first i32)
+            (if (i32.eqz (local.get $len)) (then (return (i64.const 0))))
+            (local.set $first (i32.load8_u (local.get $ptr)))
+            (if (i32.eq (local.get $first) (i32.const 108))
+              (then (loop $spin (br $spin))))
+            (if (i32.eq (local.get $first) (i32.const 103))
+              (then (if (i32.eq (memory.grow (i32.const 100)) (i32.const -1))
+                (then unreachable))))
+            (if (i32.eq (local.get $first) (i32.const 102))
+              (then (return (i64.const 68719476747))))
+            (i64.const 0)))
+    "#;
+
+    #[test]
+    #[cfg(feature = "wasm")]
+    fn wasm_plugin_test() {
+        let directory = TestDir::new("wasm");
+        let path = directory.join("plugin.wat");
+        std::fs::write(&path, WASM_PLUGIN).unwrap();
+        let plugin_manager = PluginManager::from_manifest(manifest_path())
+            .with_wasm_limits(plugin_wasm::WasmLimits {
+                fuel: 100_000,
+                max_memory_bytes: 1024 * 1024,
+            })
+            .with_path(path.to_str().unwrap(), None)
+            .unwrap()
+            .activate_plugins()
+            .unwrap();
+        assert_eq!(plugin_manager.plugins.len(), 4);
+
+        plugin_manager.execute_plugin("wasm_plugin", &()).unwrap();
+        plugin_manager
+            .execute_plugin("wasm_plugin", &"ok".to_string())
+            .unwrap();
+        let error = plugin_manager
+            .execute_plugin("wasm_plugin", &"fail".to_string())
+            .unwrap_err();
+        assert_eq!(error.to_string(), "bad context");
+
+        // Running out of fuel and exceeding the memory limit trap the guest.
+        for context in ["loop", "grow"] {
+            let error = plugin_manager
+                .execute_plugin("wasm_plugin", &context.to_string())
+                .unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<plugin_errors::PluginError>(),
+                Some(plugin_errors::PluginError::WasmTrap { plugin, .. }) if plugin == "wasm_plugin"
+            ));
+        }
+
+        // The sandbox stays usable after a trap.
+        plugin_manager.execute_plugin("wasm_plugin", &()).unwrap();
+
+        // A name outside the module's memory is rejected.
+        let path = directory.join("out_of_bounds.wat");
+        std::fs::write(
+            &path,
+            WASM_PLUGIN.replace("(i64.const 11)", "(i64.const 4294967295)"),
+        )
+        .unwrap();
+        let error = plugin_wasm::load_wasm_plugins(&path, plugin_wasm::WasmLimits::default())
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("outside its memory"), "{error}");
+    }
+
+    #[test]
+    fn lazy_loading_test() {
+        let plugin_manager = PluginManager::from_manifest(manifest_path())
+            .with_lazy_loading()
+            .activate_plugins()
+            .unwrap();
+        assert!(plugin_manager.plugins.is_empty());
+        assert!(plugin_manager.get_loaded_libraries().is_empty());
+        assert_eq!(plugin_manager.get_all_plugin_names().len(), 3);
+
+        // Only the library providing the requested plugin is opened.
+        plugin_manager.execute_plugin("plugin_b", &()).unwrap();
+        assert_eq!(
+            plugin_manager.get_loaded_libraries(),
+            vec![Path::new(&resolved_file_path("plugin_mods"))]
+        );
+        assert!(plugin_manager.get_plugin("plugin_a").is_some());
+        assert!(plugin_manager.get_plugin("missing").is_none());
+        assert_eq!(plugin_manager.get_loaded_libraries().len(), 1);
+
+        assert_eq!(plugin_manager.get_plugins_by_type_base().len(), 2);
+        assert!(plugin_manager.get_plugins_by_type_inventory().is_empty());
+        assert_eq!(plugin_manager.get_all_plugin_names_and_groups().len(), 2);
+
+        // Deregistering a plugin of an unopened library leaves it closed.
+        let mut plugin_manager = plugin_manager;
+        assert_eq!(
+            plugin_manager.deregister_plugin("inventory_a"),
+            Some("inventory_a".to_string())
+        );
+        assert!(plugin_manager.plugins.is_empty());
+        assert_eq!(plugin_manager.get_loaded_libraries().len(), 1);
+        assert_eq!(plugin_manager.get_all_plugin_names().len(), 2);
+        assert!(plugin_manager.get_plugin("inventory_a").is_none());
+        assert_eq!(plugin_manager.get_loaded_libraries().len(), 1);
+
+        assert_eq!(
+            plugin_manager.deregister_plugin("plugin_a"),
+            Some("plugin_a".to_string())
+        );
+        assert_eq!(plugin_manager.plugins.len(), 1);
+        assert_eq!(plugin_manager.get_loaded_libraries().len(), 1);
+    }
+
+    #[test]
+    fn lazy_loading_cache_test() {
+        let directory = TestDir::new("index");
+        let cache = directory.join("index.json");
+        let path = make_file_path("plugin_tasks");
+        let activate = || {
+            PluginManager::from_manifest(manifest_path())
+                .with_lazy_loading_cache(&cache)
+                .with_path(&path, None)
+                .unwrap()
+                .activate_plugins()
+                .unwrap()
+        };
+
+        // `plugin_tasks` is not indexed, so it is found by opening every
+        // unindexed library until one provides the plugin.
+        let plugin_manager = activate();
+        assert_eq!(plugin_manager.get_all_plugin_names().len(), 3);
+        assert!(plugin_manager.get_plugin("task_a").is_some());
+        drop(plugin_manager);
+
+        // The next run knows where `task_a` lives without opening anything.
+        let plugin_manager = activate();
+        assert_eq!(plugin_manager.get_all_plugin_names().len(), 4);
+        assert!(plugin_manager.get_loaded_libraries().is_empty());
+        plugin_manager.execute_plugin("task_a", &()).unwrap();
+        assert_eq!(
+            plugin_manager.get_loaded_libraries(),
+            vec![Path::new(&path)]
+        );
+        drop(plugin_manager);
+    }
+
+    #[test]
+    fn entry_point_test() {
+        let path = make_file_path("plugin_mods");
+        let entry: PluginEntry = toml::from_str(&format!(
+            "path = \"{path}\"\nentry_point = \"create_plugin\"\nmode = \"single\""
+        ))
+        .unwrap();
+        assert!(matches!(
+            &entry,
+            PluginEntry::Detailed(spec) if spec.entry_point() == "create_plugin"
+        ));
+        // Tables without a `path` key are still groups.
+        let group: PluginEntry = toml::from_str(&format!("plugin_a = \"{path}\"")).unwrap();
+        assert!(matches!(group, PluginEntry::Group(_)));
+
+        let mut plugin_manager = PluginManager::new();
+        plugin_manager
+            .activation_registration("single_a".to_string(), &entry)
+            .unwrap();
+        assert_eq!(plugin_manager.get_all_plugin_names(), vec!["plugin_a"]);
+        plugin_manager.execute_plugin("plugin_a", &()).unwrap();
+
+        let missing = PluginSpec {
+            entry_point: Some("create_missing".to_string()),
+            ..PluginSpec::from_path(&path)
+        };
+        assert!(plugin_manager.register_entry(&missing).is_err());
+    }
+
+    /// Records `on_unload` and drop calls in a shared log.
+    struct UnloadRecorder {
+        name: &'static str,
+        log: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    impl Plugin for UnloadRecorder {
+        fn name(&self) -> String {
+            self.name.to_string()
+        }
+
+        fn execute(&self, _context: &dyn Any) -> Result<(), Box<dyn std::error::Error>> {
+            Ok(())
+        }
+
+        fn on_unload(&self) {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("unload {}", self.name));
+        }
+    }
+
+    impl Drop for UnloadRecorder {
+        fn drop(&mut self) {
+            self.log.lock().unwrap().push(format!("drop {}", self.name));
+        }
+    }
+
+    #[test]
+    fn on_unload_order_test() {
+        let log = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut plugin_manager = PluginManager::new();
+        for name in ["a", "b", "c"] {
+            plugin_manager
+                .register_plugin(Plugins::Base(Box::new(UnloadRecorder {
+                    name,
+                    log: log.clone(),
+                })))
+                .unwrap();
+        }
+
+        plugin_manager.deregister_plugin("b");
+        assert_eq!(*log.lock().unwrap(), vec!["unload b", "drop b"]);
+
+        // Dropping the manager unloads the rest in reverse registration order.
+        log.lock().unwrap().clear();
+        drop(plugin_manager);
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["unload c", "drop c", "unload a", "drop a"]
+        );
+    }
+
+    #[test]
+    fn destroy_plugins_test() {
+        // A private copy, so other tests do not touch its counter.
+        let directory = TestDir::new("destroy");
+        let library = directory.join(format!(
+            "{}plugin_mods.{}",
+            std::env::consts::DLL_PREFIX,
+            std::env::consts::DLL_EXTENSION
+        ));
+        std::fs::copy(make_file_path("plugin_mods"), &library).unwrap();
+        let handle = unsafe { Library::new(&library).unwrap() };
+        let destroyed: Symbol<*const std::sync::atomic::AtomicUsize> =
+            unsafe { handle.get(b"DESTROYED_PLUGINS").unwrap() };
+        let destroyed = unsafe { &**destroyed };
+
+        let mut plugin_manager = PluginManager::new();
+        plugin_manager
+            .register_from_path(library.to_str().unwrap())
+            .unwrap();
+        plugin_manager.deregister_plugin("plugin_a");
+        assert_eq!(destroyed.load(std::sync::atomic::Ordering::SeqCst), 1);
+        drop(plugin_manager);
+        assert_eq!(destroyed.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        drop(handle);
+    }
+
+    #[test]
+    fn load_flags_test() {
+        let path = make_file_path("plugin_mods");
+        let entry: PluginEntry =
+            toml::from_str(&format!("path = \"{path}\"\nflags = [\"global\", \"now\"]")).unwrap();
+        let mut plugin_manager = PluginManager::new();
+        plugin_manager
+            .activation_registration("plugin_a_b".to_string(), &entry)
+            .unwrap();
+        plugin_manager.execute_plugin("plugin_a", &()).unwrap();
+
+        let conflicting = PluginSpec {
+            flags: vec![
+                plugin_dlopen::LoadFlag::Local,
+                plugin_dlopen::LoadFlag::Global,
+            ],
+            ..PluginSpec::from_path(&path)
+        };
+        let error = PluginManager::new()
+            .register_entry(&conflicting)
+            .unwrap_err();
+        assert!(error.to_string().contains("local and global"));
+
+        #[cfg(all(target_os = "linux", target_env = "gnu"))]
+        {
+            let deepbind = PluginSpec {
+                flags: vec![plugin_dlopen::LoadFlag::Deepbind],
+                ..PluginSpec::from_path(make_file_path("plugin_inventory"))
+            };
+            let mut plugin_manager = PluginManager::new();
+            plugin_manager.register_entry(&deepbind).unwrap();
+            assert_eq!(plugin_manager.get_all_plugin_names(), vec!["inventory_a"]);
+        }
+    }
+
+    #[test]
+    fn sha256_pinning_test() {
+        let path = make_file_path("plugin_mods");
+        let digest = plugin_shadow::sha256_file(Path::new(&path)).unwrap();
+        let entry: PluginEntry = toml::from_str(&format!(
+            "path = \"{path}\"\nsha256 = \"{}\"",
+            digest.to_uppercase()
+        ))
+        .unwrap();
+        let mut plugin_manager = PluginManager::new();
+        plugin_manager
+            .activation_registration("plugin_a_b".to_string(), &entry)
+            .unwrap();
+        assert_eq!(plugin_manager.plugins.len(), 2);
+
+        let pinned = PluginSpec {
+            sha256: Some("0".repeat(64)),
+            ..PluginSpec::from_path(&path)
+        };
+        let error = PluginManager::new().register_entry(&pinned).unwrap_err();
+        match error.downcast_ref::<plugin_errors::PluginError>() {
+            Some(plugin_errors::PluginError::IntegrityMismatch {
+                path: library,
+                expected,
+                actual,
+            }) => {
+                assert_eq!(library, Path::new(&path));
+                assert_eq!(expected, &"0".repeat(64));
+                assert_eq!(actual, &digest);
+            }
+            other => panic!("expected an integrity error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn signature_test() {
+        use ed25519_dalek::{Signer, SigningKey};
+        use plugin_signature::SignaturePolicy;
+
+        let directory = TestDir::new("signature");
+        let library = directory.join("libplugin_mods.so");
+        std::fs::copy(make_file_path("plugin_mods"), &library).unwrap();
+        let path = library.to_str().unwrap();
+
+        let signer = SigningKey::from_bytes(&[7; 32]);
+        let other = SigningKey::from_bytes(&[8; 32]).verifying_key();
+        let signature = signer.sign(&std::fs::read(&library).unwrap());
+        let encoded = plugin_signature::encode_hex(&signature.to_bytes());
+
+        // Unsigned libraries are rejected, or only warned about.
+        let enforcing = || {
+            PluginManager::new()
+                .with_trusted_keys([signer.verifying_key()])
+                .with_signature_policy(SignaturePolicy::Enforce)
+        };
+        let error = enforcing().register_from_path(path).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<plugin_errors::PluginError>(),
+            Some(plugin_errors::PluginError::SignatureRejected { .. })
+        ));
+        let mut warning = PluginManager::new().with_signature_policy(SignaturePolicy::Warn);
+        warning.register_from_path(path).unwrap();
+
+        std::fs::write(plugin_signature::signature_path(&library), encoded).unwrap();
+        let mut plugin_manager = enforcing();
+        plugin_manager.register_from_path(path).unwrap();
+        plugin_manager.execute_plugin("plugin_a", &()).unwrap();
+
+        let error = PluginManager::new()
+            .with_trusted_keys([other])
+            .with_signature_policy(SignaturePolicy::Enforce)
+            .register_from_path(path)
+            .unwrap_err();
+        assert!(error.to_string().contains("does not match any trusted key"));
+
+        drop((warning, plugin_manager));
+    }
+
+    /// Writes `bytes` to `directory/name` and returns what inspecting it finds.
+    #[cfg(target_os = "linux")]
+    fn library_problem(
+        directory: &Path,
+        name: &str,
+        bytes: &[u8],
+        spec: &PluginSpec,
+    ) -> Option<plugin_inspect::LibraryProblem> {
+        let path = directory.join(name);
+        std::fs::write(&path, bytes).unwrap();
+        match plugin_inspect::inspect_library(&path, bytes, spec) {
+            Err(plugin_errors::PluginError::InvalidLibrary { problem, .. }) => Some(problem),
+            Err(error) => panic!("unexpected error: {error}"),
+            Ok(()) => None,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn inspect_library_test() {
+        use plugin_inspect::LibraryProblem;
+
+        let directory = TestDir::new("inspect");
+        let library = make_file_path("plugin_mods");
+        let original = std::fs::read(&library).unwrap();
+        let problem = |name: &str, bytes: &[u8], spec: &PluginSpec| {
+            library_problem(&directory, name, bytes, spec)
+        };
+        let spec = PluginSpec::from_path(&library);
+
+        assert_eq!(problem("valid.so", &original, &spec), None);
+
+        let mut foreign = original.clone();
+        let machine = if std::env::consts::ARCH == "aarch64" {
+            62u16
+        } else {
+            183u16
+        };
+        foreign[18..20].copy_from_slice(&machine.to_ne_bytes());
+        assert!(matches!(
+            problem("foreign.so", &foreign, &spec),
+            Some(LibraryProblem::WrongArchitecture { .. })
+        ));
+
+        let mut other_class = original.clone();
+        other_class[4] = if cfg!(target_pointer_width = "64") {
+            1
+        } else {
+            2
+        };
+        assert!(matches!(
+            problem("other_class.so", &other_class, &spec),
+            Some(LibraryProblem::WrongClass { .. })
+        ));
+
+        let missing = PluginSpec {
+            entry_point: Some("create_missing".to_string()),
+            ..spec
+        };
+        assert_eq!(
+            problem("missing.so", &original, &missing),
+            Some(LibraryProblem::MissingEntryPoint {
+                symbol: "create_missing".to_string()
+            })
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn inspect_non_library_test() {
+        use plugin_inspect::LibraryProblem;
+
+        let directory = TestDir::new("inspect-other");
+        let library = make_file_path("plugin_mods");
+        let original = std::fs::read(&library).unwrap();
+        let problem = |name: &str, bytes: &[u8]| {
+            library_problem(&directory, name, bytes, &PluginSpec::from_path(&library))
+        };
+
+        assert_eq!(
+            problem("archive.a", b"!<arch>\nmember/"),
+            Some(LibraryProblem::StaticArchive)
+        );
+        let script = "/* GNU ld script */\nOUTPUT_FORMAT(elf64-x86-64)\nINPUT(-lplugin_mods)\n";
+        assert_eq!(
+            problem("text.so", script.as_bytes()),
+            Some(LibraryProblem::TextFile)
+        );
+        // A multibyte character cut off by the 512 byte prefix is still text.
+        let text = format!("{}é", "a".repeat(511));
+        assert_eq!(
+            problem("cut.so", text.as_bytes()),
+            Some(LibraryProblem::TextFile)
+        );
+        assert!(matches!(
+            problem("empty.so", b""),
+            Some(LibraryProblem::Malformed(_))
+        ));
+        assert!(matches!(
+            problem("short.so", &original[..16]),
+            Some(LibraryProblem::Malformed(_))
+        ));
+
+        // The manager inspects before opening the library.
+        let error = PluginManager::new()
+            .register_from_path(directory.join("text.so").to_str().unwrap())
+            .unwrap_err();
+        assert!(error.to_string().contains("is a text file"));
+    }
+
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    #[test]
+    fn library_paths_test() {
+        // libgcc_s is already mapped into the test binary; a symlink to it
+        // stands in for a dependency shipped next to the plugin.
+        let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+        let libgcc = maps
+            .lines()
+            .filter_map(|line| line.split_whitespace().nth(5))
+            .find(|path| path.ends_with("/libgcc_s.so.1"))
+            .expect("libgcc_s.so.1 is not mapped");
+
+        let directory = TestDir::new("deps");
+        std::fs::create_dir_all(directory.join("lib")).unwrap();
+        let library = directory.join("libplugin_mods.so");
+        std::fs::copy(make_file_path("plugin_mods"), &library).unwrap();
+        let dependency = directory.join("lib/libgcc_s.so.1");
+        std::os::unix::fs::symlink(libgcc, &dependency).unwrap();
+
+        let entry: PluginEntry = toml::from_str(&format!(
+            "path = \"{}\"\nlibrary_paths = [\"$ORIGIN/lib\", \"/nonexistent\"]",
+            library.display()
+        ))
+        .unwrap();
+        let PluginEntry::Detailed(spec) = &entry else {
+            panic!("expected a detailed entry");
+        };
+        assert_eq!(
+            plugin_deps::search_dirs(spec),
+            vec![directory.join("lib"), PathBuf::from("/nonexistent")]
+        );
+
+        let mut plugin_manager = PluginManager::new();
+        plugin_manager
+            .activation_registration("plugin_a_b".to_string(), &entry)
+            .unwrap();
+        assert_eq!(plugin_manager.get_preloaded_libraries(), vec![dependency]);
+        plugin_manager.execute_plugin("plugin_a", &()).unwrap();
+
+        drop(plugin_manager);
+    }
+
+    #[test]
+    fn config_source_test() {
+        let manifest = format!(
+            "[package.metadata.plugins]\nplugin_a_b = \"{}\"\n",
+            make_file_path("plugin_mods")
+        );
+        let plugin_manager = PluginManager::from_str(&manifest)
+            .activate_plugins()
+            .unwrap();
+        assert_eq!(plugin_manager.plugins.len(), 2);
+
+        let metadata = plugin_config::parse_manifest(&manifest).unwrap();
+        let plugin_manager = PluginManager::from_metadata(metadata)
+            .activate_plugins()
+            .unwrap();
+        assert_eq!(plugin_manager.plugins.len(), 2);
+
+        let error = PluginManager::from_manifest("../tests/missing/Cargo.toml")
+            .activate_plugins()
+            .err()
+            .unwrap();
+        assert!(error.to_string().starts_with("Unable to read config"));
+        assert!(
+            PluginManager::from_str("[package")
+                .activate_plugins()
+                .is_err()
+        );
+
+        let source = plugin_config::ConfigSource::File(PathBuf::from(manifest_path()));
+        let metadata = PluginManager::new()
+            .with_config_source(source)
+            .get_plugin_metadata();
+        assert_eq!(metadata.plugins.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn config_file_test() {
+        let directory = TestDir::new("config");
+        let (mods, inventory) = (
+            resolved_file_path("plugin_mods"),
+            resolved_file_path("plugin_inventory"),
+        );
+
+        let toml_file = directory.join("plugins.toml");
+        std::fs::write(
+            &toml_file,
+            format!("plugin_a_b = \"{mods}\"\n\n[inventory]\ninventory_a = \"{inventory}\"\n"),
+        )
+        .unwrap();
+        let json_file = directory.join("plugins.json");
+        let json = serde_json::json!({
+            "plugin_a_b": mods,
+            "inventory": { "inventory_a": inventory },
+        });
+        std::fs::write(&json_file, json.to_string()).unwrap();
+
+        for file in [PathBuf::from(manifest_path()), toml_file, json_file] {
+            let plugin_manager = PluginManager::from_file(&file).activate_plugins().unwrap();
+            assert_eq!(plugin_manager.plugins.len(), 3, "{}", file.display());
+        }
+
+        let error = PluginManager::from_file(directory.join("plugins.yaml"))
+            .activate_plugins()
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("expected a .toml or .json file"));
+    }
+
+    #[test]
+    fn detailed_entry_test() {
+        let (mods, inventory, tasks) = (
+            make_file_path("plugin_mods"),
+            make_file_path("plugin_inventory"),
+            make_file_path("plugin_tasks"),
+        );
+        let manifest = |requirement: &str| {
+            format!(
+                "[package.metadata.plugins.inventory]\ninventory_a = \"{inventory}\"\n\n\
+                 [package.metadata.plugins.plugin_a_b]\npath = \"{mods}\"\npriority = 10\n\
+                 version = \"{requirement}\"\n\n\
+                 [package.metadata.plugins.plugin_a_b.config]\ngreeting = \"hello\"\n\n\
+                 [package.metadata.plugins.tasks]\npath = \"{tasks}\"\nenabled = false\n"
+            )
+        };
+
+        let plugin_manager = PluginManager::from_str(&manifest("^0.1"))
+            .activate_plugins()
+            .unwrap();
+        assert_eq!(plugin_manager.plugins.len(), 3);
+        // The higher priority entry is activated first; the disabled one not at all.
+        assert_eq!(plugin_manager.registration_order[2], "inventory_a");
+        assert_eq!(
+            plugin_manager.get_entry_config("plugin_a_b"),
+            Some(&serde_json::json!({ "greeting": "hello" }))
+        );
+        assert!(plugin_manager.get_entry_config("inventory").is_none());
+
+        let error = PluginManager::from_str(&manifest(">=1.2"))
+            .activate_plugins()
+            .err()
+            .unwrap();
+        match error.downcast_ref::<plugin_errors::PluginError>() {
+            Some(plugin_errors::PluginError::VersionMismatch {
+                required, found, ..
+            }) => {
+                assert_eq!(required, ">=1.2");
+                assert_eq!(found.as_deref(), Some("0.1.0"));
+            }
+            other => panic!("expected a version error, got {other:?}"),
+        }
+
+        // Tables with a `path` key never fall back to the group form.
+        let error = toml::from_str::<PluginEntry>("path = \"a.so\"\nenabeld = false")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("invalid plugin entry"), "{error}");
+        let error = toml::from_str::<PluginEntry>("plugin_a = \"a.so\"\nplugin_b = 1")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("group member `plugin_b`"), "{error}");
+    }
+
+    #[test]
+    fn relative_path_test() {
+        // The fixture's template expands to the release build.
+        let manifest = std::fs::canonicalize(manifest_path()).unwrap();
+        let plugin_manager = PluginManager::from_manifest(&manifest);
+        let Some(PluginEntry::Individual(path)) = plugin_manager
+            .get_plugin_metadata()
+            .plugins
+            .unwrap()
+            .remove("plugin_a_b")
+        else {
+            panic!("expected an individual entry");
+        };
+        assert_eq!(path, resolved_file_path("plugin_mods"));
+    }
+
+    #[test]
+    fn relative_path_from_str_test() {
+        // Text has no directory of its own.
+        let text = "[package.metadata.plugins]\nplugin_a_b = \"libplugin_mods.so\"\n";
+        let metadata = PluginManager::from_str(text).get_plugin_metadata();
+        assert!(matches!(
+            &metadata.plugins.unwrap()["plugin_a_b"],
+            PluginEntry::Individual(path) if path == "libplugin_mods.so"
+        ));
+    }
+
+    #[test]
+    fn relative_path_error_test() {
+        // Errors name the resolved path.
+        let directory = TestDir::new("relative");
+        let config = directory.join("plugins.toml");
+        std::fs::write(&config, "missing = \"lib/libmissing.so\"\n").unwrap();
+        let error = PluginManager::from_file(&config)
+            .activate_plugins()
+            .err()
+            .unwrap();
+        let expected = directory.join("lib/libmissing.so");
+        assert!(
+            error.to_string().contains(expected.to_str().unwrap()),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn path_template_test() {
+        use plugin_config::expand_path;
+
+        let expanded = expand_path("{target_dir}/release/{dll_prefix}plugin_mods{dll_suffix}");
+        assert_eq!(
+            std::fs::canonicalize(expanded.unwrap()).unwrap(),
+            PathBuf::from(resolved_file_path("plugin_mods"))
+        );
+        assert_eq!(
+            expand_path("{profile}").unwrap(),
+            if cfg!(debug_assertions) {
+                "debug"
+            } else {
+                "release"
+            }
+        );
+
+        let home = std::env::home_dir().unwrap();
+        assert_eq!(
+            expand_path("~/plugins").unwrap(),
+            format!("{}/plugins", home.display())
+        );
+        assert_eq!(expand_path("a~b").unwrap(), "a~b");
+    }
+
+    #[test]
+    fn path_template_variables_test() {
+        use plugin_config::{expand_path, expand_path_with};
+
+        let lookup = |name: &str| match name {
+            "PLUGINS" => Some("/opt/plugins".to_string()),
+            "CARGO_TARGET_DIR" => Some("/build".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            expand_path_with("$PLUGINS/a.so", lookup).unwrap(),
+            "/opt/plugins/a.so"
+        );
+        assert_eq!(
+            expand_path_with("${PLUGINS}_b/b.so", lookup).unwrap(),
+            "/opt/plugins_b/b.so"
+        );
+        assert_eq!(
+            expand_path_with("{target_dir}/a.so", lookup).unwrap(),
+            "/build/a.so"
+        );
+        assert_eq!(expand_path_with("cost$", lookup).unwrap(), "cost$");
+
+        let error = expand_path_with("$UNSET/a.so", lookup).unwrap_err();
+        assert!(error.contains("UNSET"), "{error}");
+        let error = expand_path("{dll_sufix}").unwrap_err();
+        assert!(error.contains("dll_sufix"), "{error}");
+        assert!(expand_path("{dll_suffix").is_err());
+    }
+
+    #[test]
+    fn path_template_metadata_test() {
+        // `$ORIGIN` is left for the dependency search.
+        let text = "[package.metadata.plugins.plugin_a]\n\
+                    path = \"{dll_prefix}plugin_a{dll_suffix}\"\n\
+                    library_paths = [\"$ORIGIN/deps\", \"{dll_prefix}deps\"]\n";
+        let metadata = PluginManager::from_str(text).get_plugin_metadata();
+        let PluginEntry::Detailed(spec) = &metadata.plugins.unwrap()["plugin_a"] else {
+            panic!("expected a detailed entry");
+        };
+        assert_eq!(
+            spec.path,
+            format!(
+                "{}plugin_a{}",
+                std::env::consts::DLL_PREFIX,
+                std::env::consts::DLL_SUFFIX
+            )
+        );
+        assert_eq!(
+            spec.library_paths,
+            [
+                "$ORIGIN/deps".to_string(),
+                format!("{}deps", std::env::consts::DLL_PREFIX)
+            ]
+        );
+
+        // Errors name the entry.
+        let text = "[package.metadata.plugins]\nplugin_a = \"{platform}/a.so\"\n";
+        let error = PluginManager::from_str(text)
+            .activate_plugins()
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("plugin_a"), "{error}");
+    }
+
+    /// A directory of empty libraries, some nested, and a text file.
+    fn library_tree(tag: &str) -> TestDir {
+        let directory = TestDir::new(tag);
+        let suffix = std::env::consts::DLL_SUFFIX;
+        for file in [
+            "libb",
+            "liba",
+            "liba_test",
+            "nested/libc",
+            "nested/deeper/libd",
+        ] {
+            let path = directory.join(format!("{file}{suffix}"));
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        std::fs::write(directory.join("notes.txt"), "").unwrap();
+        directory
+    }
+
+    /// The libraries of `spec`, relative to `directory`.
+    fn library_names(directory: &Path, spec: plugin_types::DirectorySpec) -> Vec<String> {
+        spec.libraries()
+            .unwrap()
+            .iter()
+            .map(|path| {
+                let relative = path.strip_prefix(directory).unwrap();
+                relative.to_str().unwrap().replace('\\', "/")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn directory_entry_test() {
+        use plugin_types::DirectorySpec;
+
+        let directory = library_tree("directory");
+        let names = |spec| library_names(&directory, spec);
+        let suffix = std::env::consts::DLL_SUFFIX;
+        let dir = directory.to_str().unwrap();
+
+        // Sorted, library suffixes only, not recursive by default.
+        assert_eq!(
+            names(DirectorySpec::new(dir)),
+            [
+                format!("liba{suffix}"),
+                format!("liba_test{suffix}"),
+                format!("libb{suffix}")
+            ]
+        );
+        assert_eq!(
+            names(DirectorySpec::new(dir).recursive(true).exclude("*_test*")),
+            [
+                format!("liba{suffix}"),
+                format!("libb{suffix}"),
+                format!("nested/deeper/libd{suffix}"),
+                format!("nested/libc{suffix}"),
+            ]
+        );
+        // `include` narrows down the libraries, it does not add other files.
+        assert_eq!(
+            names(
+                DirectorySpec::new(dir)
+                    .recursive(true)
+                    .include("*.txt")
+                    .include("nested/*")
+            ),
+            [format!("nested/libc{suffix}")]
+        );
+        assert_eq!(
+            names(DirectorySpec::new(dir).recursive(true).exclude("nested/**")),
+            [
+                format!("liba{suffix}"),
+                format!("liba_test{suffix}"),
+                format!("libb{suffix}")
+            ]
+        );
+    }
+
+    #[test]
+    fn directory_glob_test() {
+        use plugin_types::DirectorySpec;
+
+        let directory = library_tree("directory-glob");
+        let suffix = std::env::consts::DLL_SUFFIX;
+        let dir = directory.to_str().unwrap();
+
+        // Globs split into a directory and a pattern.
+        let glob = DirectorySpec::from_glob(&format!("{dir}/nested/**/*{suffix}"));
+        assert_eq!(glob.dir, format!("{dir}/nested"));
+        assert!(glob.recursive);
+        assert_eq!(
+            library_names(&directory, glob),
+            [
+                format!("nested/deeper/libd{suffix}"),
+                format!("nested/libc{suffix}")
+            ]
+        );
+        // A glob's own pattern decides, whatever the suffix.
+        let text = DirectorySpec::from_glob(&format!("{dir}/*.txt"));
+        assert_eq!(library_names(&directory, text), ["notes.txt"]);
+        assert_eq!(DirectorySpec::from_glob("*.so").dir, ".");
+        assert_eq!(
+            DirectorySpec::from_glob("plugins"),
+            DirectorySpec::new("plugins")
+        );
+    }
+
+    #[test]
+    fn directory_entry_config_test() {
+        let directory = library_tree("directory-config");
+        let dir = directory.to_str().unwrap();
+
+        // Both entry forms, relative to the config file.
+        let config = directory.join("plugins.toml");
+        let text = "globbed = \"lib[ab]*\"\n\n\
+                    [listed]\n\
+                    dir = \"nested\"\n\
+                    recursive = true\n";
+        std::fs::write(&config, text).unwrap();
+        let plugins = PluginManager::from_file(&config)
+            .get_plugin_metadata()
+            .plugins
+            .unwrap();
+        let PluginEntry::Individual(globbed) = &plugins["globbed"] else {
+            panic!("expected an individual entry");
+        };
+        assert_eq!(*globbed, format!("{dir}/lib[ab]*"));
+        let globbed = plugin_discovery::glob_libraries(globbed).unwrap().unwrap();
+        assert_eq!(globbed.len(), 3);
+        let PluginEntry::Directory(listed) = &plugins["listed"] else {
+            panic!("expected a directory entry");
+        };
+        assert_eq!(listed.dir, format!("{dir}/nested"));
+        assert_eq!(library_names(&directory, listed.clone()).len(), 2);
+    }
+
+    #[test]
+    fn directory_entry_error_test() {
+        use plugin_types::DirectorySpec;
+
+        let directory = library_tree("directory-error");
+        let dir = directory.to_str().unwrap();
+        assert!(
+            DirectorySpec::new(dir)
+                .include("[")
+                .libraries()
+                .unwrap_err()
+                .to_string()
+                .contains("Invalid plugin pattern")
+        );
+        assert!(
+            DirectorySpec::new(directory.join("missing").to_str().unwrap())
+                .libraries()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn glob_literal_path_test() {
+        let directory = library_tree("directory-literal");
+        let suffix = std::env::consts::DLL_SUFFIX;
+        let dir = directory.to_str().unwrap();
+
+        // A path with a wildcard that exists is one library, and a glob that
+        // matches nothing is an error.
+        let literal = directory.join("[beta]").join(format!("libe{suffix}"));
+        std::fs::create_dir_all(literal.parent().unwrap()).unwrap();
+        std::fs::write(&literal, "").unwrap();
+        assert!(
+            plugin_discovery::glob_libraries(literal.to_str().unwrap())
+                .unwrap()
+                .is_none()
+        );
+        let error = plugin_discovery::glob_libraries(&format!("{dir}/*.dll_missing"))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("No plugin libraries match"), "{error}");
+    }
+
+    #[test]
+    fn with_dir_test() {
+        let directory = TestDir::new("with-dir");
+        let library = PathBuf::from(make_file_path("plugin_tasks"));
+        std::fs::copy(&library, directory.join(library.file_name().unwrap())).unwrap();
+        std::fs::write(directory.join("README.txt"), "not a plugin").unwrap();
+
+        let plugin_manager = PluginManager::from_manifest(manifest_path())
+            .with_dir(directory.to_str().unwrap(), None)
+            .unwrap()
+            .activate_plugins()
+            .unwrap();
+        assert_eq!(plugin_manager.plugins.len(), 4);
+
+        assert!(
+            PluginManager::new()
+                .with_dir(directory.join("missing").to_str().unwrap(), None)
+                .is_err()
+        );
+        drop(plugin_manager);
+    }
+
+    /// A workspace with members at several depths, an excluded package, a
+    /// stray package and a member outside the root; returns the root too.
+    fn workspace_fixture(tag: &str) -> (TestDir, PathBuf) {
+        let base = TestDir::new(tag);
+        let root = base.join("workspace");
+        let write = |path: &str, text: &str| {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
+        };
+        write(
+            "Cargo.toml",
+            "[workspace]\n\
+             members = [\"member\", \"nested/*\", \"../elsewhere\"]\n\
+             exclude = [\"excluded\"]\n\n\
+             [workspace.metadata.plugins]\n\
+             shared = \"plugins/libshared.so\"\n\
+             replaced = \"plugins/libold.so\"\n\n\
+             [workspace.metadata.plugins.inventory]\n\
+             inventory_a = \"plugins/libinventory_a.so\"\n\
+             inventory_b = \"plugins/libinventory_b.so\"\n\n\
+             [workspace.metadata.plugin_index]\n\
+             shared = [\"shared\"]\n",
+        );
+        let member = "[package]\n\
+                      name = \"member\"\n\n\
+                      [package.metadata.plugins]\n\
+                      replaced = \"libnew.so\"\n\
+                      own = \"libown.so\"\n\n\
+                      [package.metadata.plugins.inventory]\n\
+                      inventory_b = \"libinventory_b.so\"\n\
+                      inventory_c = \"libinventory_c.so\"\n";
+        write("member/Cargo.toml", member);
+        write("nested/member/Cargo.toml", member);
+        write("excluded/Cargo.toml", member);
+        write("stray/Cargo.toml", member);
+        write(
+            "../elsewhere/Cargo.toml",
+            "[package]\n\
+             name = \"elsewhere\"\n\
+             workspace = \"../workspace\"\n",
+        );
+        (base, root)
+    }
+
+    #[test]
+    fn workspace_metadata_test() {
+        let (_base, root) = workspace_fixture("workspace");
+        let path = |relative: &str| root.join(relative).to_str().unwrap().to_string();
+        let metadata = PluginManager::from_manifest(root.join("member/Cargo.toml"))
+            .config_source()
+            .load()
+            .unwrap();
+        let plugins = metadata.plugins.unwrap();
+        assert_eq!(plugins.len(), 4);
+        // Workspace paths are relative to the workspace root.
+        assert!(
+            matches!(&plugins["shared"], PluginEntry::Individual(shared) if *shared == path("plugins/libshared.so"))
+        );
+        // Member entries win.
+        assert!(
+            matches!(&plugins["replaced"], PluginEntry::Individual(replaced) if *replaced == path("member/libnew.so"))
+        );
+        assert!(
+            matches!(&plugins["own"], PluginEntry::Individual(own) if *own == path("member/libown.so"))
+        );
+        // Groups are merged member by member.
+        let PluginEntry::Group(inventory) = &plugins["inventory"] else {
+            panic!("expected a group");
+        };
+        assert_eq!(inventory.len(), 3);
+        assert_eq!(inventory["inventory_a"], path("plugins/libinventory_a.so"));
+        assert_eq!(inventory["inventory_b"], path("member/libinventory_b.so"));
+        assert!(metadata.plugin_index.unwrap().contains_key("shared"));
+    }
+
+    #[test]
+    fn workspace_root_test() {
+        let (_base, root) = workspace_fixture("workspace-root");
+        // The root is found from any depth, or named by `package.workspace`.
+        for manifest in ["nested/member/Cargo.toml", "../elsewhere/Cargo.toml"] {
+            let metadata = PluginManager::from_manifest(root.join(manifest)).get_plugin_metadata();
+            assert!(
+                metadata.plugins.unwrap().contains_key("shared"),
+                "{manifest}"
+            );
+        }
+
+        // The root manifest alone yields the workspace entries.
+        let metadata = PluginManager::from_manifest(root.join("Cargo.toml")).get_plugin_metadata();
+        assert_eq!(metadata.plugins.unwrap().len(), 3);
+    }
+
+    #[test]
+    fn workspace_non_member_test() {
+        let (_base, root) = workspace_fixture("workspace-non-member");
+        // Excluded packages and packages that are not members get none.
+        for manifest in ["excluded/Cargo.toml", "stray/Cargo.toml"] {
+            let metadata = PluginManager::from_manifest(root.join(manifest)).get_plugin_metadata();
+            assert!(
+                !metadata.plugins.unwrap().contains_key("shared"),
+                "{manifest}"
+            );
+        }
+    }
+
+    /// System, user and project files of a layered configuration.
+    fn layer_files(tag: &str) -> TestDir {
+        let directory = TestDir::new(tag);
+        std::fs::write(
+            directory.join("system.toml"),
+            "plugin_a = \"/opt/system/libplugin_a.so\"\n\
+             plugin_b = \"/opt/system/libplugin_b.so\"\n\n\
+             [inventory]\n\
+             inventory_a = \"/opt/system/libinventory_a.so\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            directory.join("user.json"),
+            r#"{
+                "plugin_b": "/home/user/libplugin_b.so",
+                "inventory": { "inventory_b": "/home/user/libinventory_b.so" }
+            }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            directory.join("Cargo.toml"),
+            "[package.metadata.plugins]\nplugin_c = \"libplugin_c.so\"\n",
+        )
+        .unwrap();
+        directory
+    }
+
+    fn layer_env(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(variable, value)| (variable.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn layered_config_test() {
+        use plugin_layers::{ConfigLayer, LayeredConfig};
+
+        let directory = layer_files("layers");
+        let layers = LayeredConfig::new("plugin_manager-layers-test")
+            .with_system_file(directory.join("system.toml"))
+            .with_user_file(directory.join("user.json"))
+            .with_project_file(directory.join("Cargo.toml"));
+        assert_eq!(layers.env_prefix(), "PLUGIN_MANAGER_LAYERS_TEST_PLUGINS__");
+        let (metadata, origins) = layers
+            .load_with_env(layer_env(&[
+                (
+                    "PLUGIN_MANAGER_LAYERS_TEST_PLUGINS__PLUGIN_A__ENABLED",
+                    "false",
+                ),
+                (
+                    "PLUGIN_MANAGER_LAYERS_TEST_PLUGINS__PLUGIN_C__PRIORITY",
+                    "5",
+                ),
+                ("OTHER_PLUGINS__PLUGIN_B__ENABLED", "false"),
+            ]))
+            .unwrap();
+        let plugins = metadata.plugins.unwrap();
+        assert_eq!(plugins.len(), 4);
+
+        let PluginEntry::Detailed(plugin_a) = &plugins["plugin_a"] else {
+            panic!("expected a detailed entry");
+        };
+        assert!(!plugin_a.enabled);
+        assert_eq!(plugin_a.path, "/opt/system/libplugin_a.so");
+        assert_eq!(origins["plugin_a"].layer, ConfigLayer::Env);
+        assert!(
+            matches!(&plugins["plugin_b"], PluginEntry::Individual(path) if path == "/home/user/libplugin_b.so")
+        );
+        assert_eq!(origins["plugin_b"].layer, ConfigLayer::User);
+        assert!(origins["plugin_b"].to_string().ends_with("user.json"));
+        let PluginEntry::Group(inventory) = &plugins["inventory"] else {
+            panic!("expected a group");
+        };
+        assert_eq!(inventory.len(), 2);
+        assert_eq!(origins["inventory"].layer, ConfigLayer::User);
+        let PluginEntry::Detailed(plugin_c) = &plugins["plugin_c"] else {
+            panic!("expected a detailed entry");
+        };
+        assert_eq!(plugin_c.priority, 5);
+        assert_eq!(
+            plugin_c.path,
+            directory.join("libplugin_c.so").to_str().unwrap()
+        );
+        assert_eq!(
+            origins["plugin_c"].source,
+            "PLUGIN_MANAGER_LAYERS_TEST_PLUGINS__PLUGIN_C__PRIORITY"
+        );
+    }
+
+    #[test]
+    fn layered_config_env_entry_test() {
+        use plugin_layers::LayeredConfig;
+
+        let directory = layer_files("layers-env");
+        let layers = LayeredConfig::new("plugin_manager-layers-env")
+            .with_system_file(directory.join("system.toml"));
+        let (metadata, _) = layers
+            .load_with_env(layer_env(&[
+                (
+                    "PLUGIN_MANAGER_LAYERS_ENV_PLUGINS__PLUGIN_D__PATH",
+                    "/opt/env/libplugin_d.so",
+                ),
+                (
+                    "PLUGIN_MANAGER_LAYERS_ENV_PLUGINS__PLUGIN_E__PATH",
+                    "env/../{dll_prefix}plugin_e{dll_suffix}",
+                ),
+            ]))
+            .unwrap();
+        let plugins = metadata.plugins.unwrap();
+        assert!(
+            matches!(&plugins["plugin_d"], PluginEntry::Detailed(spec) if spec.path == "/opt/env/libplugin_d.so")
+        );
+        // Paths from variables are expanded and resolved like those in files.
+        let PluginEntry::Detailed(plugin_e) = &plugins["plugin_e"] else {
+            panic!("expected a detailed entry");
+        };
+        let expected = std::env::current_dir().unwrap().join(format!(
+            "{}plugin_e{}",
+            std::env::consts::DLL_PREFIX,
+            std::env::consts::DLL_SUFFIX
+        ));
+        assert_eq!(plugin_e.path, expected.to_str().unwrap());
+    }
+
+    /// Layers over the plugin fixture, with missing system and user files.
+    fn run_layers() -> plugin_layers::LayeredConfig {
+        plugin_layers::LayeredConfig::new("plugin_manager-layers-run")
+            .with_system_file("missing.toml")
+            .with_user_file("missing.json")
+            .with_project_file(manifest_path())
+    }
+
+    #[test]
+    fn layered_config_disabled_group_test() {
+        let (metadata, origins) = run_layers()
+            .load_with_env(layer_env(&[(
+                "PLUGIN_MANAGER_LAYERS_RUN_PLUGINS__INVENTORY__ENABLED",
+                "false",
+            )]))
+            .unwrap();
+        assert!(!metadata.plugins.unwrap().contains_key("inventory"));
+        assert!(!origins.contains_key("inventory"));
+    }
+
+    #[test]
+    fn layered_config_origin_test() {
+        use plugin_config::ConfigSource;
+        use plugin_layers::ConfigLayer;
+
+        let plugin_manager = PluginManager::new()
+            .with_config_source(ConfigSource::Layered(run_layers()))
+            .activate_plugins()
+            .unwrap();
+        assert_eq!(plugin_manager.plugins.len(), 3);
+        let origin = plugin_manager.get_entry_origin("plugin_a_b").unwrap();
+        assert_eq!(origin.layer, ConfigLayer::Project);
+        assert!(
+            PluginManager::new()
+                .get_entry_origin("plugin_a_b")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn layered_config_hyphenated_name_test() {
+        use plugin_layers::{ConfigLayer, LayeredConfig};
+
+        // Variable names cannot contain `-`, so `_` stands in for it.
+        let directory = TestDir::new("layers-hyphen");
+        std::fs::write(
+            directory.join("system.toml"),
+            "plugin-a = \"/opt/system/libplugin_a.so\"\n",
+        )
+        .unwrap();
+        let layers = LayeredConfig::new("plugin_manager-layers-hyphen")
+            .with_system_file(directory.join("system.toml"));
+        let (metadata, origins) = layers
+            .load_with_env(layer_env(&[(
+                "PLUGIN_MANAGER_LAYERS_HYPHEN_PLUGINS__PLUGIN_A__ENABLED",
+                "false",
+            )]))
+            .unwrap();
+        let plugins = metadata.plugins.unwrap();
+        assert_eq!(plugins.len(), 1);
+        let PluginEntry::Detailed(plugin_a) = &plugins["plugin-a"] else {
+            panic!("expected a detailed entry");
+        };
+        assert!(!plugin_a.enabled);
+        assert_eq!(origins["plugin-a"].layer, ConfigLayer::Env);
+    }
+
+    #[test]
+    fn layered_config_invalid_env_test() {
+        use plugin_layers::LayeredConfig;
+
+        // Malformed and invalid overrides name the variable.
+        let directory = layer_files("layers-bad");
+        let layers = LayeredConfig::new("plugin_manager-layers-bad")
+            .with_system_file(directory.join("system.toml"));
+        let error = layers
+            .load_with_env(layer_env(&[(
+                "PLUGIN_MANAGER_LAYERS_BAD_PLUGINS__PLUGIN_A",
+                "1",
+            )]))
+            .unwrap_err();
+        assert!(error.to_string().contains("<NAME>__<FIELD>"), "{error}");
+        let error = layers
+            .load_with_env(layer_env(&[(
+                "PLUGIN_MANAGER_LAYERS_BAD_PLUGINS__PLUGIN_A__COLOUR",
+                "red",
+            )]))
+            .unwrap_err();
+        assert!(error.to_string().contains("__COLOUR"), "{error}");
+    }
 }
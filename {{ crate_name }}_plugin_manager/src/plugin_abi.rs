@@ -0,0 +1,218 @@
+//! ABI handshake between the host and plugin libraries.
+//!
+//! Every plugin library exports a [`AbiDescriptor`] under the
+//! [`ABI_DESCRIPTOR_SYMBOL`] name, usually through the [`declare_plugin_abi!`]
+//! macro. `PluginManager::load_plugin` reads and validates it before any other
+//! symbol of the library is called.
+//!
+//! [`declare_plugin_abi!`]: crate::declare_plugin_abi
+
+use crate::plugin_errors::PluginError;
+use libloading::Library;
+use semver::{Version, VersionReq};
+use std::ffi::{CStr, c_char};
+use std::path::Path;
+
+/// Name of the symbol holding the plugin's [`AbiDescriptor`].
+pub const ABI_DESCRIPTOR_SYMBOL: &[u8] = b"PLUGIN_ABI_DESCRIPTOR";
+
+/// Version of the [`AbiDescriptor`] struct itself.
+pub const ABI_DESCRIPTOR_VERSION: u32 = 1;
+
+/// Version of the `Plugins` enum and plugin traits layout. Bump this whenever
+/// `plugin_types::Plugins`, `Plugin` or `PluginInventory` change.
+pub const PLUGINS_LAYOUT_VERSION: u32 = 2;
+
+/// Version of this crate, nul terminated.
+pub const CRATE_VERSION: &CStr = to_cstr(concat!(env!("CARGO_PKG_VERSION"), "\0"));
+
+/// Version of the compiler that built this crate, nul terminated.
+pub const RUSTC_VERSION: &CStr = to_cstr(concat!(env!("PLUGIN_MANAGER_RUSTC_VERSION"), "\0"));
+
+/// Describes the build a plugin library was compiled with.
+///
+/// The layout of this struct is `#[repr(C)]` and must only ever grow at the end;
+/// `descriptor_version` is always read first.
+#[repr(C)]
+#[derive(Debug)]
+pub struct AbiDescriptor {
+    pub descriptor_version: u32,
+    pub layout_version: u32,
+    pub crate_version: *const c_char,
+    pub rustc_version: *const c_char,
+    pub plugin_version: *const c_char,
+}
+
+// The descriptor only holds pointers to static, immutable strings.
+unsafe impl Sync for AbiDescriptor {}
+
+impl AbiDescriptor {
+    /// Builds the descriptor for the current build; `plugin_version` is the
+    /// nul terminated version of the plugin crate.
+    pub const fn new(plugin_version: &'static CStr) -> Self {
+        AbiDescriptor {
+            descriptor_version: ABI_DESCRIPTOR_VERSION,
+            layout_version: PLUGINS_LAYOUT_VERSION,
+            crate_version: CRATE_VERSION.as_ptr(),
+            rustc_version: RUSTC_VERSION.as_ptr(),
+            plugin_version: plugin_version.as_ptr(),
+        }
+    }
+
+    /// The version of the plugin crate that exported this descriptor.
+    pub fn plugin_version(&self) -> String {
+        unsafe { read_cstr(self.plugin_version) }
+    }
+
+    /// Checks that the descriptor was produced by a build compatible with the host.
+    pub fn validate(&self, path: &Path) -> Result<(), PluginError> {
+        let mismatch = |field, expected: String, found: String| PluginError::AbiMismatch {
+            path: path.to_path_buf(),
+            field,
+            expected,
+            found,
+        };
+
+        if self.descriptor_version != ABI_DESCRIPTOR_VERSION {
+            return Err(mismatch(
+                "descriptor version",
+                ABI_DESCRIPTOR_VERSION.to_string(),
+                self.descriptor_version.to_string(),
+            ));
+        }
+        if self.layout_version != PLUGINS_LAYOUT_VERSION {
+            return Err(mismatch(
+                "Plugins layout version",
+                PLUGINS_LAYOUT_VERSION.to_string(),
+                self.layout_version.to_string(),
+            ));
+        }
+
+        let rustc_version = unsafe { read_cstr(self.rustc_version) };
+        if rustc_version.as_bytes() != RUSTC_VERSION.to_bytes() {
+            return Err(mismatch(
+                "rustc version",
+                RUSTC_VERSION.to_string_lossy().into_owned(),
+                rustc_version,
+            ));
+        }
+
+        let crate_version = unsafe { read_cstr(self.crate_version) };
+        let host_version = CRATE_VERSION.to_string_lossy();
+        if !versions_compatible(&host_version, &crate_version) {
+            return Err(mismatch(
+                "plugin-manager crate version",
+                host_version.into_owned(),
+                crate_version,
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Reads and validates the ABI descriptor exported by `library`.
+pub fn check_library_abi<'lib>(
+    library: &'lib Library,
+    path: &Path,
+) -> Result<&'lib AbiDescriptor, PluginError> {
+    let descriptor = unsafe { library.get::<*const AbiDescriptor>(ABI_DESCRIPTOR_SYMBOL) }
+        .map_err(|_| PluginError::MissingAbiDescriptor {
+            path: path.to_path_buf(),
+        })?;
+    let descriptor = unsafe { &**descriptor };
+    descriptor.validate(path)?;
+    log::debug!(
+        "ABI descriptor accepted for {} (plugin version {})",
+        path.display(),
+        descriptor.plugin_version()
+    );
+    Ok(descriptor)
+}
+
+/// Checks the plugin crate version in `descriptor` against the `version`
+/// requirement of its entry.
+pub fn check_plugin_version(
+    descriptor: &AbiDescriptor,
+    requirement: &VersionReq,
+    path: &Path,
+) -> Result<(), PluginError> {
+    let found = descriptor.plugin_version();
+    match Version::parse(&found) {
+        Ok(version) if requirement.matches(&version) => Ok(()),
+        _ => Err(PluginError::VersionMismatch {
+            path: path.to_path_buf(),
+            required: requirement.to_string(),
+            found: Some(found),
+        }),
+    }
+}
+
+/// The error for a `version` requirement on a plugin that does not report
+/// its version, i.e. C ABI and WASM plugins.
+pub fn unknown_plugin_version(requirement: &VersionReq, path: &Path) -> PluginError {
+    PluginError::VersionMismatch {
+        path: path.to_path_buf(),
+        required: requirement.to_string(),
+        found: None,
+    }
+}
+
+/// Semver compatibility of two crate versions: the major version must match,
+/// and while it is `0` the minor version must match as well.
+fn versions_compatible(host: &str, plugin: &str) -> bool {
+    let parts = |version: &str| -> Vec<String> {
+        version
+            .split(['-', '+'])
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .map(str::to_string)
+            .collect()
+    };
+    let (host, plugin) = (parts(host), parts(plugin));
+    match (host.first(), plugin.first()) {
+        (Some(host_major), Some(plugin_major)) if host_major == plugin_major => {
+            host_major != "0" || host.get(1) == plugin.get(1)
+        }
+        _ => false,
+    }
+}
+
+unsafe fn read_cstr(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        return String::from("<missing>");
+    }
+    unsafe { CStr::from_ptr(ptr) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+const fn to_cstr(bytes: &'static str) -> &'static CStr {
+    match CStr::from_bytes_with_nul(bytes.as_bytes()) {
+        Ok(cstr) => cstr,
+        Err(_) => panic!("version string contains an interior nul byte"),
+    }
+}
+
+/// Exports the [`AbiDescriptor`] every plugin library must provide.
+///
+/// Call it once at the root of the plugin crate:
+///
+/// ```rust
+/// plugin_manager::declare_plugin_abi!();
+/// ```
+#[macro_export]
+macro_rules! declare_plugin_abi {
+    () => {
+        #[unsafe(no_mangle)]
+        pub static PLUGIN_ABI_DESCRIPTOR: $crate::plugin_abi::AbiDescriptor =
+            $crate::plugin_abi::AbiDescriptor::new(
+                match ::std::ffi::CStr::from_bytes_with_nul(
+                    concat!(env!("CARGO_PKG_VERSION"), "\0").as_bytes(),
+                ) {
+                    Ok(version) => version,
+                    Err(_) => panic!("invalid plugin version"),
+                },
+            );
+    };
+}
@@ -0,0 +1,613 @@
+//! Where `activate_plugins` reads the plugin metadata from.
+//!
+//! The host chooses the source when creating the manager:
+//!
+//! ```rust
+//! use plugin_manager::{Metadata, PluginManager};
+//!
+//! // A Cargo manifest with `[package.metadata.plugins]`.
+//! let from_manifest = PluginManager::from_manifest("../tests/plugin_mods/Cargo.toml");
+//! // A standalone plugins.toml or plugins.json.
+//! let from_file = PluginManager::from_file("/etc/my_app/plugins.toml");
+//! // Manifest text, e.g. embedded with `include_str!`.
+//! let from_text = PluginManager::from_str("[package.metadata.plugins]\n");
+//! // Metadata built by the host itself.
+//! let from_metadata = PluginManager::from_metadata(Metadata::default());
+//! // System, user, project and environment layers, see `plugin_layers`.
+//! let from_layers = PluginManager::from_layers("my_app");
+//! ```
+//!
+//! Standalone files hold the entries of `[package.metadata.plugins]` at the
+//! top level, with the same [`PluginEntry`](crate::plugin_types::PluginEntry)
+//! forms:
+//!
+//! ```toml
+//! plugin_a_b = "/opt/my_app/plugins/libplugin_mods.so"
+//!
+//! [inventory]
+//! inventory_a = "/opt/my_app/plugins/libplugin_inventory.so"
+//! ```
+//!
+//! ```json
+//! {
+//!     "plugin_a_b": "/opt/my_app/plugins/libplugin_mods.so",
+//!     "inventory": { "inventory_a": "/opt/my_app/plugins/libplugin_inventory.so" }
+//! }
+//! ```
+//!
+//! [`ConfigFormat::detect`] picks the format from the file name.
+//!
+//! Paths may contain placeholders, so one configuration works on every
+//! platform, see [`expand_path`]:
+//!
+//! ```toml
+//! [package.metadata.plugins]
+//! plugin_a = "{target_dir}/{profile}/{dll_prefix}plugin_a{dll_suffix}"
+//! plugin_b = "~/.local/share/my_app/plugins/{dll_prefix}plugin_b{dll_suffix}"
+//! plugin_c = "${PLUGIN_DIR}/{dll_prefix}plugin_c{dll_suffix}"
+//! ```
+//!
+//! Relative paths in a file are resolved against the directory of that file,
+//! so the same configuration works from any working directory. Paths from
+//! [`ConfigSource::Str`] and [`ConfigSource::Metadata`] stay relative to the
+//! working directory.
+//!
+//! A manifest inside a Cargo workspace also gets the entries of
+//! `[workspace.metadata.plugins]` from the workspace root, see
+//! [`find_workspace_root`]. Entries of the package replace workspace entries
+//! of the same name, except that groups are merged member by member, see
+//! [`Metadata::merge`]. Relative workspace paths are resolved against the
+//! workspace root.
+//!
+//! `PluginManager::new()` keeps reading the manifest named by the
+//! `CARGO_MANIFEST_PATH` environment variable, and finds no metadata when it
+//! is unset.
+
+use crate::plugin_discovery::{MATCH_OPTIONS, is_glob};
+use crate::plugin_layers::{ConfigOrigin, LayeredConfig};
+use crate::plugin_types::{GroupOrName, PathString, PluginEntry};
+use crate::{Metadata, PluginManager};
+use glob::Pattern;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+/// Environment variable naming the manifest read by [`ConfigSource::Env`].
+pub const MANIFEST_PATH_VAR: &str = "CARGO_MANIFEST_PATH";
+
+/// The format of a configuration file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    /// A Cargo manifest with `[package.metadata.plugins]`.
+    Manifest,
+    /// Plugin entries at the top level of a TOML file.
+    Toml,
+    /// Plugin entries at the top level of a JSON object.
+    Json,
+}
+
+impl ConfigFormat {
+    /// `Cargo*.toml` files are manifests, other `.toml` files standalone TOML
+    /// and `.json` files JSON.
+    pub fn detect(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") if file_name.starts_with("Cargo") => Ok(ConfigFormat::Manifest),
+            Some("toml") => Ok(ConfigFormat::Toml),
+            Some("json") => Ok(ConfigFormat::Json),
+            _ => Err(format!(
+                "Unable to detect the format of {}; expected a .toml or .json file",
+                path.display()
+            )
+            .into()),
+        }
+    }
+}
+
+/// A source of plugin metadata.
+#[derive(Debug, Clone)]
+pub enum ConfigSource {
+    /// A Cargo manifest file.
+    Manifest(PathBuf),
+    /// A manifest or standalone file, in the format detected from its name.
+    File(PathBuf),
+    /// The text of a Cargo manifest.
+    Str(String),
+    /// Metadata that needs no parsing.
+    Metadata(Metadata),
+    /// The manifest named by `CARGO_MANIFEST_PATH`, if it is set.
+    Env,
+    /// System, user, project and environment layers, see `plugin_layers`.
+    Layered(LayeredConfig),
+}
+
+impl ConfigSource {
+    /// Reads and parses the metadata, expanding placeholders in its paths.
+    pub fn load(&self) -> Result<Metadata, Box<dyn std::error::Error>> {
+        match self {
+            ConfigSource::Manifest(path) => read_config(path, ConfigFormat::Manifest),
+            ConfigSource::File(path) => read_config(path, ConfigFormat::detect(path)?),
+            ConfigSource::Str(manifest) => {
+                let mut metadata = parse_manifest(manifest)?;
+                metadata.expand_paths()?;
+                Ok(metadata)
+            }
+            ConfigSource::Metadata(metadata) => {
+                let mut metadata = metadata.clone();
+                metadata.expand_paths()?;
+                Ok(metadata)
+            }
+            ConfigSource::Layered(layers) => Ok(layers.load()?.0),
+            ConfigSource::Env => match std::env::var_os(MANIFEST_PATH_VAR) {
+                Some(path) => read_config(Path::new(&path), ConfigFormat::Manifest),
+                None => {
+                    log::debug!("{MANIFEST_PATH_VAR} is not set, no plugin metadata");
+                    Ok(Metadata::default())
+                }
+            },
+        }
+    }
+
+    /// Like [`ConfigSource::load`], also returning the layer each entry came
+    /// from. Only layered sources have origins.
+    pub fn load_with_origins(
+        &self,
+    ) -> Result<(Metadata, HashMap<GroupOrName, ConfigOrigin>), Box<dyn std::error::Error>> {
+        match self {
+            ConfigSource::Layered(layers) => layers.load(),
+            _ => Ok((self.load()?, HashMap::new())),
+        }
+    }
+}
+
+impl PluginManager {
+    /// A manager configured by the manifest at `path`.
+    pub fn from_manifest(path: impl Into<PathBuf>) -> Self {
+        Self::new().with_config_source(ConfigSource::Manifest(path.into()))
+    }
+
+    /// A manager configured by the file at `path`, a Cargo manifest or a
+    /// standalone `.toml` or `.json` file.
+    pub fn from_file(path: impl Into<PathBuf>) -> Self {
+        Self::new().with_config_source(ConfigSource::File(path.into()))
+    }
+
+    /// A manager configured by the manifest text `manifest`, parsed when the
+    /// plugins are activated.
+    // Not `FromStr`: parsing is deferred to `activate_plugins`.
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(manifest: &str) -> Self {
+        Self::new().with_config_source(ConfigSource::Str(manifest.to_string()))
+    }
+
+    /// A manager configured by `metadata`.
+    pub fn from_metadata(metadata: Metadata) -> Self {
+        Self::new().with_config_source(ConfigSource::Metadata(metadata))
+    }
+
+    pub fn with_config_source(mut self, source: ConfigSource) -> Self {
+        self.config_source = source;
+        self
+    }
+
+    pub fn config_source(&self) -> &ConfigSource {
+        &self.config_source
+    }
+}
+
+pub(crate) fn read_config(
+    path: &Path,
+    format: ConfigFormat,
+) -> Result<Metadata, Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|error| format!("Unable to read config {}: {error}", path.display()))?;
+    let base = config_dir(path);
+    if format != ConfigFormat::Manifest {
+        let mut metadata = parse_config(&text, format).map_err(|error| invalid(path, error))?;
+        prepare(&mut metadata, &base).map_err(|error| invalid(path, error))?;
+        return Ok(metadata);
+    }
+    let manifest: toml::Value =
+        toml::from_str(&text).map_err(|error| invalid(path, error.into()))?;
+    let mut metadata = manifest_metadata(&manifest).map_err(|error| invalid(path, error))?;
+    prepare(&mut metadata, &base).map_err(|error| invalid(path, error))?;
+    match find_workspace_root(&base, &manifest)? {
+        Some(root) => Ok(read_workspace_metadata(&root)?.merge(metadata)),
+        None => Ok(metadata),
+    }
+}
+
+fn invalid(path: &Path, error: Box<dyn std::error::Error>) -> String {
+    format!("Invalid config {}: {error}", path.display())
+}
+
+/// The absolute directory of a configuration file, which its relative paths
+/// are resolved against.
+fn config_dir(path: &Path) -> PathBuf {
+    let directory = path.parent().unwrap_or(Path::new(""));
+    std::fs::canonicalize(directory)
+        .or_else(|_| std::path::absolute(directory))
+        .unwrap_or_else(|_| directory.to_path_buf())
+}
+
+fn prepare(metadata: &mut Metadata, base: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    metadata.expand_paths()?;
+    metadata.resolve_paths(base);
+    Ok(())
+}
+
+/// The root manifest of the workspace the package at `dir` belongs to, found
+/// as Cargo does: the directory named by `package.workspace`, or else the
+/// nearest parent directory whose `Cargo.toml` has a `[workspace]` table and
+/// does not exclude the package. The package must match one of the root's
+/// `workspace.members`. `None` for manifests that are a workspace root
+/// themselves, virtual manifests and packages outside any workspace.
+pub fn find_workspace_root(
+    dir: &Path,
+    manifest: &toml::Value,
+) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+    if manifest.get("workspace").is_some() {
+        return Ok(None);
+    }
+    let Some(package) = manifest.get("package") else {
+        return Ok(None);
+    };
+    if let Some(root) = package.get("workspace").and_then(|root| root.as_str()) {
+        let root = normalize(&dir.join(root));
+        let candidate = root.join("Cargo.toml");
+        let text = std::fs::read_to_string(&candidate)
+            .map_err(|error| format!("Unable to read config {}: {error}", candidate.display()))?;
+        let value: toml::Value =
+            toml::from_str(&text).map_err(|error| invalid(&candidate, error.into()))?;
+        let Some(workspace) = value.get("workspace") else {
+            return Ok(None);
+        };
+        if !member_of(&root, workspace, dir) {
+            warn_not_member(&root, dir);
+            return Ok(None);
+        }
+        return Ok(Some(candidate));
+    }
+    for ancestor in dir.ancestors().skip(1) {
+        let candidate = ancestor.join("Cargo.toml");
+        let Ok(text) = std::fs::read_to_string(&candidate) else {
+            continue;
+        };
+        let value: toml::Value =
+            toml::from_str(&text).map_err(|error| invalid(&candidate, error.into()))?;
+        let Some(workspace) = value.get("workspace") else {
+            continue;
+        };
+        if member_of(ancestor, workspace, dir) {
+            return Ok(Some(candidate));
+        }
+        let relative = dir.strip_prefix(ancestor).unwrap_or(dir);
+        let excluded =
+            workspace_paths(workspace, "exclude").any(|exclude| relative.starts_with(exclude));
+        // Cargo keeps looking above a workspace that excludes the package.
+        if !excluded {
+            warn_not_member(ancestor, dir);
+            return Ok(None);
+        }
+    }
+    Ok(None)
+}
+
+/// Whether the package at `dir` matches one of the `workspace.members` of the
+/// workspace at `root`, which may be globs such as `crates/*`.
+fn member_of(root: &Path, workspace: &toml::Value, dir: &Path) -> bool {
+    let relative = dir.strip_prefix(root).ok();
+    workspace_paths(workspace, "members").any(|member| {
+        let member = member.trim_end_matches(['/', '\\']);
+        if normalize(&root.join(member)) == dir {
+            return true;
+        }
+        is_glob(member)
+            && relative.is_some_and(|relative| {
+                Pattern::new(member)
+                    .is_ok_and(|pattern| pattern.matches_path_with(relative, MATCH_OPTIONS))
+            })
+    })
+}
+
+fn warn_not_member(root: &Path, dir: &Path) {
+    log::warn!(
+        "{} is not a member of the workspace at {}; ignoring the workspace metadata",
+        dir.display(),
+        root.display()
+    );
+}
+
+fn workspace_paths<'a>(workspace: &'a toml::Value, key: &str) -> impl Iterator<Item = &'a str> {
+    workspace
+        .get(key)
+        .and_then(|paths| paths.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|path| path.as_str())
+}
+
+/// Reads `[workspace.metadata]` from the root manifest at `path`, with
+/// relative paths resolved against the workspace root.
+fn read_workspace_metadata(path: &Path) -> Result<Metadata, Box<dyn std::error::Error>> {
+    log::debug!("Reading workspace plugin metadata from {}", path.display());
+    let text = std::fs::read_to_string(path)
+        .map_err(|error| format!("Unable to read config {}: {error}", path.display()))?;
+    let manifest: toml::Value =
+        toml::from_str(&text).map_err(|error| invalid(path, error.into()))?;
+    let mut metadata =
+        table_metadata(&manifest, "workspace").map_err(|error| invalid(path, error))?;
+    prepare(&mut metadata, &config_dir(path)).map_err(|error| invalid(path, error))?;
+    Ok(metadata)
+}
+
+impl Metadata {
+    /// Combines workspace metadata with a member's. Entries of `overrides`
+    /// replace the entries of the same name, except that two groups are
+    /// merged member by member. `plugin_index` entries are replaced by name.
+    pub fn merge(mut self, overrides: Metadata) -> Metadata {
+        if let Some(entries) = overrides.plugins {
+            let plugins = self.plugins.get_or_insert_with(HashMap::new);
+            for (group_or_name, entry) in entries {
+                match (plugins.get_mut(&group_or_name), entry) {
+                    (Some(PluginEntry::Group(members)), PluginEntry::Group(overrides)) => {
+                        members.extend(overrides);
+                    }
+                    (_, entry) => {
+                        plugins.insert(group_or_name, entry);
+                    }
+                }
+            }
+        }
+        if let Some(index) = overrides.plugin_index {
+            self.plugin_index
+                .get_or_insert_with(HashMap::new)
+                .extend(index);
+        }
+        self
+    }
+
+    /// Expands the placeholders in the paths of every entry.
+    pub fn expand_paths(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        for (group_or_name, entry) in self.plugins.iter_mut().flatten() {
+            let expand = |path: &mut PathString| -> Result<(), Box<dyn std::error::Error>> {
+                *path = expand_path(path)
+                    .map_err(|error| format!("Plugin entry {group_or_name}: {error}"))?;
+                Ok(())
+            };
+            match entry {
+                PluginEntry::Individual(path) => expand(path)?,
+                PluginEntry::Detailed(spec) => {
+                    expand(&mut spec.path)?;
+                    for directory in &mut spec.library_paths {
+                        if !is_origin_relative(directory) {
+                            expand(directory)?;
+                        }
+                    }
+                }
+                PluginEntry::Group(members) => {
+                    for path in members.values_mut() {
+                        expand(path)?;
+                    }
+                }
+                PluginEntry::Directory(spec) => {
+                    expand(&mut spec.dir)?;
+                    for pattern in spec.include.iter_mut().chain(&mut spec.exclude) {
+                        expand(pattern)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes the relative paths of every entry relative to `base` instead of
+    /// the working directory.
+    pub fn resolve_paths(&mut self, base: &Path) {
+        for (group_or_name, entry) in self.plugins.iter_mut().flatten() {
+            match entry {
+                PluginEntry::Individual(path) => resolve(base, group_or_name, path),
+                PluginEntry::Detailed(spec) => {
+                    resolve(base, group_or_name, &mut spec.path);
+                    for directory in &mut spec.library_paths {
+                        if !is_origin_relative(directory) {
+                            resolve(base, group_or_name, directory);
+                        }
+                    }
+                }
+                PluginEntry::Group(members) => {
+                    for (name, path) in members {
+                        resolve(base, name, path);
+                    }
+                }
+                PluginEntry::Directory(spec) => resolve(base, group_or_name, &mut spec.dir),
+            }
+        }
+    }
+}
+
+/// Expands and resolves one path of an entry like [`Metadata::expand_paths`]
+/// and [`Metadata::resolve_paths`] do.
+pub(crate) fn prepare_path(
+    base: &Path,
+    name: &str,
+    path: &mut PathString,
+) -> Result<(), Box<dyn std::error::Error>> {
+    *path = expand_path(path).map_err(|error| format!("Plugin entry {name}: {error}"))?;
+    resolve(base, name, path);
+    Ok(())
+}
+
+pub(crate) fn is_origin_relative(directory: &str) -> bool {
+    directory.starts_with("$ORIGIN") || directory.starts_with("${ORIGIN}")
+}
+
+/// Expands the placeholders in a plugin path:
+///
+/// | placeholder      | value                                                          |
+/// |------------------|----------------------------------------------------------------|
+/// | `{dll_prefix}`   | `lib` on Unix, empty on Windows                                |
+/// | `{dll_suffix}`   | `.so`, `.dylib` or `.dll`                                      |
+/// | `{profile}`      | the Cargo profile the host was built with, e.g. `release`      |
+/// | `{target_dir}`   | `CARGO_TARGET_DIR`, or the target directory of the host build  |
+/// | `$VAR`, `${VAR}` | the environment variable `VAR`                                 |
+/// | leading `~`      | the home directory                                             |
+///
+/// Unknown placeholders and unset variables are errors.
+///
+/// `{profile}` and the fallback for `{target_dir}` are recorded when the host
+/// is compiled, so they name a directory on the build machine. They suit
+/// `cargo run` and tests; an installed host should use `$VAR` or relative
+/// paths instead, or set `CARGO_TARGET_DIR` at runtime.
+pub fn expand_path(template: &str) -> Result<String, String> {
+    expand_path_with(template, |name| std::env::var(name).ok())
+}
+
+/// Expands the placeholders in a plugin path like [`expand_path`], reading
+/// variables, including `CARGO_TARGET_DIR`, with `lookup` instead of from the
+/// environment.
+pub fn expand_path_with(
+    template: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<String, String> {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    if let Some(after) = rest.strip_prefix('~')
+        && (after.is_empty() || after.starts_with(['/', '\\']))
+    {
+        let home = std::env::home_dir().ok_or("the home directory is unknown")?;
+        expanded.push_str(&home.to_string_lossy());
+        rest = after;
+    }
+    while let Some(start) = rest.find(['{', '$']) {
+        expanded.push_str(&rest[..start]);
+        let (value, length) = if rest[start..].starts_with('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed placeholder in {template}"))?;
+            (
+                placeholder(&rest[start + 1..start + end], &lookup)?,
+                end + 1,
+            )
+        } else if rest[start + 1..].starts_with('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed variable in {template}"))?;
+            (variable(&rest[start + 2..start + end], &lookup)?, end + 1)
+        } else {
+            let name_length = rest[start + 1..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len() - start - 1);
+            if name_length == 0 {
+                ("$".to_string(), 1)
+            } else {
+                (
+                    variable(&rest[start + 1..start + 1 + name_length], &lookup)?,
+                    name_length + 1,
+                )
+            }
+        };
+        expanded.push_str(&value);
+        rest = &rest[start + length..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+fn placeholder(name: &str, lookup: &impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    Ok(match name {
+        "dll_prefix" => std::env::consts::DLL_PREFIX.to_string(),
+        "dll_suffix" => std::env::consts::DLL_SUFFIX.to_string(),
+        "profile" => option_env!("PLUGIN_MANAGER_PROFILE")
+            .ok_or("the build profile is unknown")?
+            .to_string(),
+        "target_dir" => match lookup("CARGO_TARGET_DIR") {
+            Some(target_dir) => target_dir,
+            None => option_env!("PLUGIN_MANAGER_TARGET_DIR")
+                .ok_or("the target directory is unknown; set CARGO_TARGET_DIR")?
+                .to_string(),
+        },
+        _ => {
+            return Err(format!(
+                "unknown placeholder `{}`",
+                "{".to_string() + name + "}"
+            ));
+        }
+    })
+}
+
+fn variable(name: &str, lookup: &impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    lookup(name).ok_or_else(|| format!("environment variable {name} is not set"))
+}
+
+fn resolve(base: &Path, name: &str, path: &mut PathString) {
+    if Path::new(path.as_str()).is_absolute() {
+        return;
+    }
+    let resolved = normalize(&base.join(path.as_str()));
+    log::debug!(
+        "Resolved plugin path for {name}: {path} -> {}",
+        resolved.display()
+    );
+    *path = resolved.to_string_lossy().into_owned();
+}
+
+/// Removes `.` and `..` components without touching the file system, as
+/// Cargo does for paths in manifests.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push(component);
+                }
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Parses the text of a configuration file.
+pub fn parse_config(
+    text: &str,
+    format: ConfigFormat,
+) -> Result<Metadata, Box<dyn std::error::Error>> {
+    let plugins: HashMap<GroupOrName, PluginEntry> = match format {
+        ConfigFormat::Manifest => return parse_manifest(text),
+        ConfigFormat::Toml => toml::from_str(text)?,
+        ConfigFormat::Json => serde_json::from_str(text)?,
+    };
+    Ok(Metadata {
+        plugins: Some(plugins),
+        plugin_index: None,
+    })
+}
+
+/// Extracts the plugin metadata from the text of a Cargo manifest, see
+/// [`manifest_metadata`].
+pub fn parse_manifest(manifest: &str) -> Result<Metadata, Box<dyn std::error::Error>> {
+    manifest_metadata(&toml::from_str(manifest)?)
+}
+
+/// `[package.metadata]` of a manifest merged over its own
+/// `[workspace.metadata]`, see [`Metadata::merge`].
+pub fn manifest_metadata(manifest: &toml::Value) -> Result<Metadata, Box<dyn std::error::Error>> {
+    let workspace = table_metadata(manifest, "workspace")?;
+    let package = table_metadata(manifest, "package")?;
+    Ok(workspace.merge(package))
+}
+
+fn table_metadata(
+    manifest: &toml::Value,
+    table: &str,
+) -> Result<Metadata, Box<dyn std::error::Error>> {
+    match manifest.get(table).and_then(|table| table.get("metadata")) {
+        Some(metadata) => Ok(metadata.clone().try_into()?),
+        None => Ok(Metadata::default()),
+    }
+}
@@ -0,0 +1,143 @@
+//! Native dependencies of plugin libraries.
+//!
+//! A plugin that links against its own shared libraries would normally need
+//! `LD_LIBRARY_PATH` set for the whole host. Instead, its entry can list
+//! directories to search, where `$ORIGIN` stands for the directory of the
+//! plugin library, as in an `RPATH`:
+//!
+//! ```toml
+//! [package.metadata.plugins.plugin_a]
+//! path = "/opt/plugins/plugin_a/libplugin_a.so"
+//! library_paths = ["$ORIGIN", "$ORIGIN/../lib"]
+//! ```
+//!
+//! Before opening the plugin, the manager reads its `DT_NEEDED` entries and
+//! opens every dependency found in those directories, dependencies first. The
+//! dynamic loader then reuses the already loaded libraries when it opens the
+//! plugin. Libraries not found there are left to the system search path.
+//! Preloaded libraries stay open until the `PluginManager` is dropped, after
+//! all plugin libraries are closed.
+
+use crate::PluginManager;
+use crate::plugin_dlopen;
+use crate::plugin_inspect::{self, HOST_IS_ELF};
+use crate::plugin_types::PluginSpec;
+use libloading::Library;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+/// Dependency libraries opened on behalf of plugins, in load order.
+#[derive(Debug, Default)]
+pub struct PreloadedLibraries {
+    libraries: Mutex<Vec<(PathBuf, Library)>>,
+}
+
+impl PreloadedLibraries {
+    fn lock(&self) -> MutexGuard<'_, Vec<(PathBuf, Library)>> {
+        self.libraries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Paths of the preloaded libraries, in load order.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.lock().iter().map(|(path, _)| path.clone()).collect()
+    }
+
+    /// Closes every preloaded library in reverse load order.
+    pub(crate) fn close_all(&self) {
+        let mut libraries = self.lock();
+        while let Some((path, library)) = libraries.pop() {
+            log::debug!("Closing preloaded library: {}", path.display());
+            drop(library);
+        }
+    }
+}
+
+/// The search directories of `spec`, with `$ORIGIN` replaced by the
+/// directory of `spec.path`.
+pub fn search_dirs(spec: &PluginSpec) -> Vec<PathBuf> {
+    let origin = Path::new(&spec.path)
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    spec.library_paths
+        .iter()
+        .map(|dir| {
+            let rest = dir
+                .strip_prefix("$ORIGIN")
+                .or_else(|| dir.strip_prefix("${ORIGIN}"));
+            match rest {
+                Some(rest) => origin.join(rest.trim_start_matches('/')),
+                None => PathBuf::from(dir),
+            }
+        })
+        .collect()
+}
+
+impl PluginManager {
+    /// Paths of the dependency libraries preloaded for plugins.
+    pub fn get_preloaded_libraries(&self) -> Vec<PathBuf> {
+        self.preloaded.paths()
+    }
+
+    /// Opens the dependencies of `filename` found in the search directories of
+    /// `spec`.
+    pub(crate) fn preload_dependencies(
+        &self,
+        filename: &Path,
+        bytes: &[u8],
+        spec: &PluginSpec,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if spec.library_paths.is_empty() {
+            return Ok(());
+        }
+        if !HOST_IS_ELF {
+            log::warn!(
+                "Ignoring library_paths for {}: only supported for ELF libraries",
+                spec.path
+            );
+            return Ok(());
+        }
+        let dirs = search_dirs(spec);
+        self.preload_needed(filename, bytes, spec, &dirs, &mut HashSet::new())
+    }
+
+    fn preload_needed(
+        &self,
+        library: &Path,
+        bytes: &[u8],
+        spec: &PluginSpec,
+        dirs: &[PathBuf],
+        seen: &mut HashSet<String>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for name in plugin_inspect::needed_libraries(library, bytes)? {
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            let Some(candidate) = dirs.iter().map(|dir| dir.join(&name)).find(|p| p.is_file())
+            else {
+                continue;
+            };
+            let candidate_bytes = std::fs::read(&candidate)
+                .map_err(|error| format!("Unable to read {}: {error}", candidate.display()))?;
+            self.preload_needed(&candidate, &candidate_bytes, spec, dirs, seen)?;
+
+            let mut preloaded = self.preloaded.lock();
+            if preloaded.iter().any(|(path, _)| path == &candidate) {
+                continue;
+            }
+            log::debug!("Preloading {} for {}", candidate.display(), spec.path);
+            let handle = plugin_dlopen::open_library(&candidate, &[]).map_err(|error| {
+                format!(
+                    "Unable to preload {} for plugin {}: {error}",
+                    candidate.display(),
+                    spec.path
+                )
+            })?;
+            preloaded.push((candidate, handle));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,260 @@
+//! Plugin entries that name a directory instead of a single library.
+//!
+//! A string entry containing `*`, `?` or `[` is a glob unless it names an
+//! existing file, so `plugins/[beta]/libfoo.so` still loads that one library.
+//! A glob that matches no library is an error. A table with a `dir` key lists
+//! a directory with options:
+//!
+//! ```toml
+//! [package.metadata.plugins]
+//! extras = "plugins/*.so"
+//! tools = "plugins/tools/**/*.so"
+//!
+//! [package.metadata.plugins.vendor]
+//! dir = "/opt/my_app/plugins"
+//! recursive = true
+//! include = ["lib*"]
+//! exclude = ["*_test*", "disabled/**"]
+//! ```
+//!
+//! `activate_plugins` loads every matching file in the order of its path
+//! relative to `dir`, so the same directory always loads in the same order.
+//! Patterns without a `/` are matched against the file name and patterns with
+//! one against the relative path, where `**` matches any number of
+//! directories. Only files with the platform's library suffix (and `.wasm`
+//! with the `wasm` feature) are loaded, narrowed down by `include` when given;
+//! a glob string selects files by its pattern alone. Symbolic links to
+//! directories are not followed.
+//!
+//! The host can add a directory with [`PluginManager::with_dir`].
+
+use crate::PluginManager;
+use crate::plugin_types::{DirectorySpec, PathString, PluginEntry};
+use glob::{MatchOptions, Pattern};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+const GLOB_CHARACTERS: [char; 3] = ['*', '?', '['];
+
+pub(crate) const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Whether a path contains a glob wildcard.
+pub fn is_glob(path: &str) -> bool {
+    path.contains(GLOB_CHARACTERS)
+}
+
+/// The libraries matching a string entry, or `None` when it is the path of
+/// one library: it has no wildcard, or names an existing file.
+pub(crate) fn glob_libraries(
+    path: &str,
+) -> Result<Option<Vec<PathBuf>>, Box<dyn std::error::Error>> {
+    if !is_glob(path) || Path::new(path).exists() {
+        return Ok(None);
+    }
+    let libraries = DirectorySpec::from_glob(path).libraries()?;
+    if libraries.is_empty() {
+        return Err(format!("No plugin libraries match {path}").into());
+    }
+    Ok(Some(libraries))
+}
+
+impl DirectorySpec {
+    /// All files with a library suffix directly in `dir`.
+    pub fn new(dir: impl Into<PathString>) -> Self {
+        DirectorySpec {
+            dir: dir.into(),
+            recursive: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            glob: false,
+        }
+    }
+
+    /// Splits a glob such as `plugins/**/*.so` into the directory before the
+    /// first wildcard and an include pattern for the rest. A glob with more
+    /// than a file name pattern searches recursively.
+    pub fn from_glob(glob: &str) -> Self {
+        let components: Vec<&str> = glob.split(['/', '\\']).collect();
+        let Some(first) = components.iter().position(|component| is_glob(component)) else {
+            return DirectorySpec::new(glob);
+        };
+        let dir = match components[..first].join("/") {
+            dir if dir.is_empty() && glob.starts_with(['/', '\\']) => "/".to_string(),
+            dir if dir.is_empty() => ".".to_string(),
+            dir => dir,
+        };
+        let pattern = components[first..].join("/");
+        DirectorySpec {
+            dir,
+            recursive: pattern.contains('/'),
+            include: vec![pattern],
+            exclude: Vec::new(),
+            glob: true,
+        }
+    }
+
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.recursive = recursive;
+        self
+    }
+
+    pub fn include(mut self, pattern: impl Into<String>) -> Self {
+        self.include.push(pattern.into());
+        self
+    }
+
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.exclude.push(pattern.into());
+        self
+    }
+
+    /// The matching files, sorted by their path relative to `dir`.
+    pub fn libraries(&self) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+        let include = compile(&self.include)?;
+        let exclude = compile(&self.exclude)?;
+        let dir = Path::new(&self.dir);
+        if !dir.is_dir() {
+            return Err(format!("Plugin directory does not exist: {}", self.dir).into());
+        }
+        let mut files = Vec::new();
+        self.walk(dir, "", &mut files)?;
+        files.sort();
+        let libraries: Vec<PathBuf> = files
+            .into_iter()
+            .filter(|relative| {
+                let included = if self.glob {
+                    matches_any(&include, relative)
+                } else {
+                    has_library_suffix(relative)
+                        && (include.is_empty() || matches_any(&include, relative))
+                };
+                included && !matches_any(&exclude, relative)
+            })
+            .map(|relative| dir.join(relative))
+            .collect();
+        if libraries.is_empty() {
+            log::warn!("No plugin libraries found in {}", self.dir);
+        }
+        Ok(libraries)
+    }
+
+    /// Collects the `/` separated paths of the files below `dir`.
+    fn walk(
+        &self,
+        dir: &Path,
+        prefix: &str,
+        files: &mut Vec<String>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let entries = std::fs::read_dir(dir).map_err(|error| {
+            format!("Unable to read plugin directory {}: {error}", dir.display())
+        })?;
+        for entry in entries {
+            let entry = entry?;
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                log::warn!("Skipping non-Unicode file name in {}", dir.display());
+                continue;
+            };
+            let relative = format!("{prefix}{name}");
+            if entry.file_type()?.is_dir() {
+                if self.recursive {
+                    self.walk(&entry.path(), &format!("{relative}/"), files)?;
+                }
+            } else if entry.path().is_file() {
+                files.push(relative);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<&str> for DirectorySpec {
+    /// A glob, or a plain directory.
+    fn from(path: &str) -> Self {
+        DirectorySpec::from_glob(path)
+    }
+}
+
+fn compile(patterns: &[String]) -> Result<Vec<Pattern>, Box<dyn std::error::Error>> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            Pattern::new(pattern)
+                .map_err(|error| format!("Invalid plugin pattern `{pattern}`: {error}").into())
+        })
+        .collect()
+}
+
+fn matches_any(patterns: &[Pattern], relative: &str) -> bool {
+    let file_name = relative.rsplit('/').next().unwrap_or(relative);
+    patterns.iter().any(|pattern| {
+        let candidate = if pattern.as_str().contains('/') {
+            relative
+        } else {
+            file_name
+        };
+        pattern.matches_with(candidate, MATCH_OPTIONS)
+    })
+}
+
+fn has_library_suffix(relative: &str) -> bool {
+    relative.ends_with(std::env::consts::DLL_SUFFIX)
+        || (cfg!(feature = "wasm") && relative.ends_with(".wasm"))
+}
+
+/// The plugin name of a discovered library: its file name without the
+/// platform's library prefix and suffix.
+pub(crate) fn library_name(path: &Path) -> String {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    match stem.strip_prefix(std::env::consts::DLL_PREFIX) {
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => stem,
+    }
+}
+
+impl PluginManager {
+    /// Adds the libraries in a directory, or matching a glob, to the ones
+    /// loaded by `activate_plugins`:
+    ///
+    /// ```rust,no_run
+    /// use plugin_manager::PluginManager;
+    /// use plugin_manager::plugin_types::DirectorySpec;
+    ///
+    /// let plugin_manager = PluginManager::new()
+    ///     .with_dir("plugins/*.so", None)?
+    ///     .with_dir(
+    ///         DirectorySpec::new("/opt/my_app/plugins")
+    ///             .recursive(true)
+    ///             .exclude("*_test*"),
+    ///         Some("vendor"),
+    ///     )?
+    ///     .activate_plugins()?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn with_dir(
+        mut self,
+        spec: impl Into<DirectorySpec>,
+        group: Option<&str>,
+    ) -> Result<Self, Error> {
+        let spec = spec.into();
+        if !Path::new(&spec.dir).is_dir() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("Plugin directory does not exist: {}", spec.dir),
+            ));
+        }
+        let group_or_name = group.unwrap_or("base").to_string();
+        self.plugin_path.push(HashMap::from([(
+            group_or_name,
+            PluginEntry::Directory(spec),
+        )]));
+        Ok(self)
+    }
+}
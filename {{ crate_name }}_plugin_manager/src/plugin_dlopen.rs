@@ -0,0 +1,109 @@
+//! `dlopen` flags for plugin libraries.
+//!
+//! `Library::new` opens libraries with `RTLD_LAZY | RTLD_LOCAL`. A detailed
+//! `PluginEntry` can choose other flags, e.g. `RTLD_GLOBAL` for a library
+//! whose symbols other plugins link against, or `RTLD_DEEPBIND` to keep a
+//! plugin on its own copies of symbols the host also defines:
+//!
+//! ```toml
+//! [package.metadata.plugins.plugin_a]
+//! path = "target/release/libplugin_a.so"
+//! flags = ["global", "now"]
+//! ```
+//!
+//! A missing binding flag defaults to `lazy` and a missing visibility flag to
+//! `local`. The flags only apply on Unix; elsewhere they are ignored with a
+//! warning.
+
+use libloading::Library;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// A flag passed to `dlopen`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadFlag {
+    /// `RTLD_LOCAL`: the library's symbols do not resolve later libraries.
+    Local,
+    /// `RTLD_GLOBAL`: the library's symbols resolve later libraries.
+    Global,
+    /// `RTLD_NOW`: resolve every symbol when the library is opened.
+    Now,
+    /// `RTLD_LAZY`: resolve functions on first call.
+    Lazy,
+    /// `RTLD_DEEPBIND`: prefer the library's own symbols over global ones
+    /// (Linux with glibc only).
+    Deepbind,
+}
+
+/// Opens `path` with `flags`, or with `Library::new` when there are none.
+pub fn open_library(
+    path: &Path,
+    flags: &[LoadFlag],
+) -> Result<Library, Box<dyn std::error::Error>> {
+    if flags.is_empty() {
+        return Ok(unsafe { Library::new(path)? });
+    }
+    open_with_flags(path, flags)
+}
+
+#[cfg(unix)]
+fn open_with_flags(path: &Path, flags: &[LoadFlag]) -> Result<Library, Box<dyn std::error::Error>> {
+    use libloading::os::unix;
+
+    let has = |flag| flags.contains(&flag);
+    if has(LoadFlag::Local) && has(LoadFlag::Global) {
+        return Err(format!(
+            "Conflicting load flags for {}: local and global",
+            path.display()
+        )
+        .into());
+    }
+    if has(LoadFlag::Now) && has(LoadFlag::Lazy) {
+        return Err(format!(
+            "Conflicting load flags for {}: now and lazy",
+            path.display()
+        )
+        .into());
+    }
+
+    let mut mode = if has(LoadFlag::Now) {
+        unix::RTLD_NOW
+    } else {
+        unix::RTLD_LAZY
+    };
+    mode |= if has(LoadFlag::Global) {
+        unix::RTLD_GLOBAL
+    } else {
+        unix::RTLD_LOCAL
+    };
+    if has(LoadFlag::Deepbind) {
+        mode |= deepbind(path)?;
+    }
+    log::debug!("Opening {} with dlopen mode {mode:#x}", path.display());
+    let library = unsafe { unix::Library::open(Some(path), mode)? };
+    Ok(library.into())
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+fn deepbind(_path: &Path) -> Result<std::os::raw::c_int, Box<dyn std::error::Error>> {
+    Ok(libc::RTLD_DEEPBIND)
+}
+
+#[cfg(all(unix, not(all(target_os = "linux", target_env = "gnu"))))]
+fn deepbind(path: &Path) -> Result<std::os::raw::c_int, Box<dyn std::error::Error>> {
+    Err(format!(
+        "Load flag deepbind for {} is not supported on this platform",
+        path.display()
+    )
+    .into())
+}
+
+#[cfg(not(unix))]
+fn open_with_flags(path: &Path, flags: &[LoadFlag]) -> Result<Library, Box<dyn std::error::Error>> {
+    log::warn!(
+        "Ignoring load flags {flags:?} for {}: only supported on Unix",
+        path.display()
+    );
+    Ok(unsafe { Library::new(path)? })
+}
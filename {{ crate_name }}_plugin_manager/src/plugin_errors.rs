@@ -0,0 +1,193 @@
+use crate::plugin_inspect::LibraryProblem;
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+
+/// Errors raised by the `PluginManager` while loading or talking to plugins.
+///
+/// Most public methods return `Box<dyn std::error::Error>`; these values can be
+/// recovered with `downcast_ref::<PluginError>()` when the caller needs to
+/// react to a specific failure.
+#[derive(Debug)]
+pub enum PluginError {
+    /// The library does not export the `PLUGIN_ABI_DESCRIPTOR` symbol.
+    MissingAbiDescriptor { path: PathBuf },
+    /// A field of the plugin's ABI descriptor differs from the host's.
+    AbiMismatch {
+        path: PathBuf,
+        field: &'static str,
+        expected: String,
+        found: String,
+    },
+    /// The plugin crate version does not satisfy the entry's `version`
+    /// requirement; `found` is `None` for plugins without an ABI descriptor.
+    VersionMismatch {
+        path: PathBuf,
+        required: String,
+        found: Option<String>,
+    },
+    /// The execution context cannot be passed across the C ABI.
+    UnsupportedContext { plugin: String },
+    /// A C ABI plugin returned a status or plugin kind outside the values
+    /// defined in `plugin_ffi`.
+    InvalidFfiValue { field: &'static str, value: u32 },
+    /// Plugin code panicked. `plugin` is the entry point's symbol when the
+    /// panic happened before any plugin was created.
+    PluginPanicked {
+        plugin: String,
+        library: Option<PathBuf>,
+        message: String,
+    },
+    /// The helper process hosting an isolated plugin library died.
+    HostCrashed { library: PathBuf, status: String },
+    /// The library's SHA-256 digest differs from the one pinned in its entry.
+    IntegrityMismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+    /// The library's detached signature is missing, malformed or not made by
+    /// a trusted key.
+    SignatureRejected { path: PathBuf, reason: String },
+    /// Pre-flight inspection found that the file is not a loadable plugin
+    /// library, see `plugin_inspect`.
+    InvalidLibrary {
+        path: PathBuf,
+        problem: LibraryProblem,
+    },
+    /// A WASM plugin trapped, ran out of fuel or exceeded its memory limit.
+    WasmTrap {
+        plugin: String,
+        library: PathBuf,
+        message: String,
+    },
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PluginError::MissingAbiDescriptor { path } => write!(
+                f,
+                "Plugin {} does not export an ABI descriptor; rebuild it with `declare_plugin_abi!()`",
+                path.display()
+            ),
+            PluginError::AbiMismatch {
+                path,
+                field,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Plugin {} is incompatible: {field} mismatch (host: {expected}, plugin: {found})",
+                path.display()
+            ),
+            PluginError::VersionMismatch {
+                path,
+                required,
+                found: Some(found),
+            } => write!(
+                f,
+                "Plugin {} has version {found}, which does not satisfy {required}",
+                path.display()
+            ),
+            PluginError::VersionMismatch {
+                path,
+                required,
+                found: None,
+            } => write!(
+                f,
+                "Plugin {} does not report its version, required {required}",
+                path.display()
+            ),
+            PluginError::UnsupportedContext { plugin } => write!(
+                f,
+                "Context type is not supported by C ABI plugin '{plugin}'; use (), String, &str, Vec<u8> or serde_json::Value"
+            ),
+            PluginError::InvalidFfiValue { field, value } => {
+                write!(f, "C ABI plugin returned an invalid {field}: {value}")
+            }
+            PluginError::PluginPanicked {
+                plugin,
+                library,
+                message,
+            } => {
+                write!(f, "Plugin '{plugin}' panicked")?;
+                if let Some(library) = library {
+                    write!(f, " (library {})", library.display())?;
+                }
+                write!(f, ": {message}")
+            }
+            PluginError::HostCrashed { library, status } => write!(
+                f,
+                "Plugin host for {} crashed ({status})",
+                library.display()
+            ),
+            PluginError::IntegrityMismatch {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Plugin {} failed its integrity check: expected sha256 {expected}, found {actual}",
+                path.display()
+            ),
+            PluginError::SignatureRejected { path, reason } => write!(
+                f,
+                "Plugin {} failed signature verification: {reason}",
+                path.display()
+            ),
+            PluginError::InvalidLibrary { path, problem } => {
+                write!(f, "Cannot load plugin {}: {problem}", path.display())
+            }
+            PluginError::WasmTrap {
+                plugin,
+                library,
+                message,
+            } => write!(
+                f,
+                "WASM plugin '{plugin}' from {} trapped: {message}",
+                library.display()
+            ),
+        }
+    }
+}
+
+impl Error for PluginError {}
+
+/// Runs `call`, turning a panic into `PluginError::PluginPanicked`.
+pub fn catch_panic<T>(plugin: &str, call: impl FnOnce() -> T) -> Result<T, PluginError> {
+    panic::catch_unwind(AssertUnwindSafe(call)).map_err(|payload| PluginError::PluginPanicked {
+        plugin: plugin.to_string(),
+        library: None,
+        message: panic_message(payload.as_ref()),
+    })
+}
+
+/// Fills in the library of a `PluginPanicked` error that does not name one yet.
+pub fn with_library(error: Box<dyn Error>, path: Option<&std::path::Path>) -> Box<dyn Error> {
+    match (error.downcast::<PluginError>(), path) {
+        (Ok(mut error), Some(path)) => {
+            if let PluginError::PluginPanicked { library, .. } = error.as_mut()
+                && library.is_none()
+            {
+                *library = Some(path.to_path_buf());
+            }
+            error
+        }
+        (Ok(error), None) => error,
+        (Err(error), _) => error,
+    }
+}
+
+/// Extracts the message from a panic payload.
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("unknown panic payload")
+    }
+}
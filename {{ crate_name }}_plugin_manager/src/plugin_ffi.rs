@@ -0,0 +1,537 @@
+//! Stable C ABI plugin interface.
+//!
+//! The Rust ABI used by `create_plugins` passes `Vec`, `Box<dyn Plugin>` and
+//! `String` values across the library boundary, which is only sound when the
+//! host and the plugin were built by the same compiler. Plugins exported with
+//! [`export_ffi_plugins!`] instead hand out `#[repr(C)]` descriptors whose
+//! memory is always released by the side that allocated it. The host wraps
+//! them in [`FfiPluginHandle`], which implements `Plugin` and `PluginInventory`
+//! so the rest of the manager does not know the difference.
+//!
+//! Contexts are passed to FFI plugins as bytes, see [`encode_context`]; the
+//! plugin receives them as a `Vec<u8>` through its `execute` context.
+//!
+//! [`export_ffi_plugins!`]: crate::export_ffi_plugins
+
+use crate::plugin_errors::{PluginError, panic_message};
+use crate::plugin_types::{Plugin, PluginInventory, Plugins};
+use libloading::Library;
+use std::any::Any;
+use std::ffi::c_void;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+/// Name of the symbol holding the plugin's [`FfiPluginDescriptor`].
+pub const FFI_DESCRIPTOR_SYMBOL: &[u8] = b"PLUGIN_FFI_DESCRIPTOR";
+
+/// Version of the C ABI described in this module.
+pub const FFI_ABI_VERSION: u32 = 1;
+
+/// A borrowed byte slice.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct FfiStr {
+    pub ptr: *const u8,
+    pub len: usize,
+}
+
+impl FfiStr {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        FfiStr {
+            ptr: bytes.as_ptr(),
+            len: bytes.len(),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` and `len` must describe memory that is valid for `'a`.
+    pub unsafe fn as_bytes<'a>(&self) -> &'a [u8] {
+        if self.ptr.is_null() {
+            &[]
+        } else {
+            unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+        }
+    }
+}
+
+/// An owned UTF-8 string, freed by the allocator that created it.
+#[repr(C)]
+pub struct FfiString {
+    pub ptr: *mut u8,
+    pub len: usize,
+    pub capacity: usize,
+    pub free: Option<unsafe extern "C" fn(*mut u8, usize, usize)>,
+}
+
+impl FfiString {
+    pub fn new(value: String) -> Self {
+        let mut bytes = std::mem::ManuallyDrop::new(value.into_bytes());
+        FfiString {
+            ptr: bytes.as_mut_ptr(),
+            len: bytes.len(),
+            capacity: bytes.capacity(),
+            free: Some(free_string),
+        }
+    }
+
+    /// Copies the string into host memory and releases the original.
+    pub fn into_string(self) -> String {
+        let bytes = unsafe {
+            FfiStr {
+                ptr: self.ptr,
+                len: self.len,
+            }
+            .as_bytes()
+        };
+        String::from_utf8_lossy(bytes).into_owned()
+    }
+}
+
+impl Drop for FfiString {
+    fn drop(&mut self) {
+        if let Some(free) = self.free.take() {
+            unsafe { free(self.ptr, self.len, self.capacity) };
+        }
+    }
+}
+
+unsafe extern "C" fn free_string(ptr: *mut u8, len: usize, capacity: usize) {
+    drop(unsafe { Vec::from_raw_parts(ptr, len, capacity) });
+}
+
+/// `FfiResult::status` of a successful call.
+pub const FFI_STATUS_OK: u32 = 0;
+/// `FfiResult::status` of a call that returned an error.
+pub const FFI_STATUS_ERROR: u32 = 1;
+/// `FfiResult::status` of a call that panicked.
+pub const FFI_STATUS_PANIC: u32 = 2;
+
+/// Outcome of a call into an FFI plugin, decoded from `FfiResult::status`.
+///
+/// Statuses cross the C ABI as plain `u32` values, since a foreign plugin
+/// returning an undeclared discriminant in a Rust enum would be undefined
+/// behavior.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FfiStatus {
+    Ok,
+    Error,
+    Panic,
+}
+
+impl TryFrom<u32> for FfiStatus {
+    type Error = PluginError;
+
+    fn try_from(value: u32) -> Result<Self, PluginError> {
+        match value {
+            FFI_STATUS_OK => Ok(FfiStatus::Ok),
+            FFI_STATUS_ERROR => Ok(FfiStatus::Error),
+            FFI_STATUS_PANIC => Ok(FfiStatus::Panic),
+            _ => Err(PluginError::InvalidFfiValue {
+                field: "status",
+                value,
+            }),
+        }
+    }
+}
+
+impl From<FfiStatus> for u32 {
+    fn from(status: FfiStatus) -> u32 {
+        match status {
+            FfiStatus::Ok => FFI_STATUS_OK,
+            FfiStatus::Error => FFI_STATUS_ERROR,
+            FfiStatus::Panic => FFI_STATUS_PANIC,
+        }
+    }
+}
+
+/// Status plus a message: the error text, the panic message, or the return
+/// value of calls such as `name`.
+#[repr(C)]
+pub struct FfiResult {
+    /// One of the `FFI_STATUS_*` values.
+    pub status: u32,
+    pub message: FfiString,
+}
+
+impl FfiResult {
+    fn ok(message: String) -> Self {
+        FfiResult {
+            status: FFI_STATUS_OK,
+            message: FfiString::new(message),
+        }
+    }
+
+    fn error(message: String) -> Self {
+        FfiResult {
+            status: FFI_STATUS_ERROR,
+            message: FfiString::new(message),
+        }
+    }
+
+    fn panicked(payload: Box<dyn Any + Send>) -> Self {
+        FfiResult {
+            status: FFI_STATUS_PANIC,
+            message: FfiString::new(panic_message(payload.as_ref())),
+        }
+    }
+}
+
+/// `FfiPlugin::kind` of a base plugin.
+pub const FFI_PLUGIN_BASE: u32 = 0;
+/// `FfiPlugin::kind` of an inventory plugin.
+pub const FFI_PLUGIN_INVENTORY: u32 = 1;
+
+/// Which `Plugins` variant an FFI plugin represents, decoded from
+/// `FfiPlugin::kind`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FfiPluginKind {
+    Base,
+    Inventory,
+}
+
+impl TryFrom<u32> for FfiPluginKind {
+    type Error = PluginError;
+
+    fn try_from(value: u32) -> Result<Self, PluginError> {
+        match value {
+            FFI_PLUGIN_BASE => Ok(FfiPluginKind::Base),
+            FFI_PLUGIN_INVENTORY => Ok(FfiPluginKind::Inventory),
+            _ => Err(PluginError::InvalidFfiValue {
+                field: "plugin kind",
+                value,
+            }),
+        }
+    }
+}
+
+impl From<FfiPluginKind> for u32 {
+    fn from(kind: FfiPluginKind) -> u32 {
+        match kind {
+            FfiPluginKind::Base => FFI_PLUGIN_BASE,
+            FfiPluginKind::Inventory => FFI_PLUGIN_INVENTORY,
+        }
+    }
+}
+
+/// A plugin instance together with the functions operating on it.
+#[repr(C)]
+pub struct FfiPlugin {
+    pub instance: *mut c_void,
+    /// One of the `FFI_PLUGIN_*` values.
+    pub kind: u32,
+    pub name: unsafe extern "C" fn(*const c_void) -> FfiResult,
+    pub execute: unsafe extern "C" fn(*const c_void, FfiStr) -> FfiResult,
+    pub load: unsafe extern "C" fn(*const c_void) -> FfiResult,
+    pub drop: unsafe extern "C" fn(*mut c_void),
+}
+
+/// The plugins created by a library, freed by the library.
+#[repr(C)]
+pub struct FfiPluginList {
+    pub ptr: *mut FfiPlugin,
+    pub len: usize,
+    pub capacity: usize,
+    pub free: unsafe extern "C" fn(*mut FfiPlugin, usize, usize),
+    /// Set when creating the plugins failed; `ptr` is then empty.
+    pub error: FfiResult,
+}
+
+/// Entry point exported by FFI plugin libraries.
+#[repr(C)]
+pub struct FfiPluginDescriptor {
+    pub abi_version: u32,
+    pub create_plugins: unsafe extern "C" fn() -> FfiPluginList,
+}
+
+impl FfiPluginDescriptor {
+    pub const fn new(create_plugins: unsafe extern "C" fn() -> FfiPluginList) -> Self {
+        FfiPluginDescriptor {
+            abi_version: FFI_ABI_VERSION,
+            create_plugins,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Plugin side
+// ---------------------------------------------------------------------------
+
+/// Converts the plugins returned by `create` into an [`FfiPluginList`].
+///
+/// Used by [`export_ffi_plugins!`](crate::export_ffi_plugins); panics raised
+/// by `create` are reported through `FfiPluginList::error`.
+pub fn export_plugins(create: fn() -> Vec<Plugins>) -> FfiPluginList {
+    let (plugins, error) = match panic::catch_unwind(create) {
+        Ok(plugins) => (plugins, FfiResult::ok(String::new())),
+        Err(payload) => (Vec::new(), FfiResult::panicked(payload)),
+    };
+    let plugins: Vec<FfiPlugin> = plugins.into_iter().map(export_plugin).collect();
+    let mut plugins = std::mem::ManuallyDrop::new(plugins);
+    FfiPluginList {
+        ptr: plugins.as_mut_ptr(),
+        len: plugins.len(),
+        capacity: plugins.capacity(),
+        free: free_plugin_list,
+        error,
+    }
+}
+
+fn export_plugin(plugin: Plugins) -> FfiPlugin {
+    let kind = match plugin {
+        Plugins::Base(_) => FFI_PLUGIN_BASE,
+        Plugins::Inventory(_) => FFI_PLUGIN_INVENTORY,
+    };
+    FfiPlugin {
+        instance: Box::into_raw(Box::new(plugin)) as *mut c_void,
+        kind,
+        name: plugin_name,
+        execute: plugin_execute,
+        load: plugin_load,
+        drop: plugin_drop,
+    }
+}
+
+fn guarded(call: impl FnOnce() -> FfiResult) -> FfiResult {
+    panic::catch_unwind(AssertUnwindSafe(call)).unwrap_or_else(FfiResult::panicked)
+}
+
+unsafe extern "C" fn plugin_name(instance: *const c_void) -> FfiResult {
+    let plugin = unsafe { &*(instance as *const Plugins) };
+    guarded(|| FfiResult::ok(plugin.name()))
+}
+
+unsafe extern "C" fn plugin_execute(instance: *const c_void, context: FfiStr) -> FfiResult {
+    let plugin = unsafe { &*(instance as *const Plugins) };
+    let context = unsafe { context.as_bytes() }.to_vec();
+    guarded(|| match plugin.execute(&context) {
+        Ok(()) => FfiResult::ok(String::new()),
+        Err(error) => FfiResult::error(error.to_string()),
+    })
+}
+
+unsafe extern "C" fn plugin_load(instance: *const c_void) -> FfiResult {
+    let plugin = unsafe { &*(instance as *const Plugins) };
+    guarded(|| {
+        if let Plugins::Inventory(inventory) = plugin {
+            inventory.load();
+        }
+        FfiResult::ok(String::new())
+    })
+}
+
+/// Runs `Plugin::on_unload` and drops the plugin. The host's `on_unload` is a
+/// no-op for C ABI plugins, so the hook still runs before the plugin is dropped.
+unsafe extern "C" fn plugin_drop(instance: *mut c_void) {
+    let plugin = unsafe { Box::from_raw(instance as *mut Plugins) };
+    if let Err(error) = plugin.on_unload() {
+        log::error!("{error}");
+    }
+    if panic::catch_unwind(AssertUnwindSafe(move || drop(plugin))).is_err() {
+        log::error!("Plugin panicked while being dropped");
+    }
+}
+
+unsafe extern "C" fn free_plugin_list(ptr: *mut FfiPlugin, len: usize, capacity: usize) {
+    drop(unsafe { Vec::from_raw_parts(ptr, len, capacity) });
+}
+
+/// Exports the plugins returned by `$create` (a `fn() -> Vec<Plugins>`)
+/// through the stable C ABI.
+///
+/// ```rust
+/// use plugin_manager::plugin_types::Plugins;
+///
+/// fn create_plugins() -> Vec<Plugins> {
+///     Vec::new()
+/// }
+///
+/// plugin_manager::export_ffi_plugins!(create_plugins);
+/// ```
+#[macro_export]
+macro_rules! export_ffi_plugins {
+    ($create:path) => {
+        #[unsafe(no_mangle)]
+        pub static PLUGIN_FFI_DESCRIPTOR: $crate::plugin_ffi::FfiPluginDescriptor = {
+            unsafe extern "C" fn create_ffi_plugins() -> $crate::plugin_ffi::FfiPluginList {
+                $crate::plugin_ffi::export_plugins($create)
+            }
+            $crate::plugin_ffi::FfiPluginDescriptor::new(create_ffi_plugins)
+        };
+    };
+}
+
+// ---------------------------------------------------------------------------
+// Host side
+// ---------------------------------------------------------------------------
+
+/// Host-side wrapper presenting an [`FfiPlugin`] as a `Plugin`.
+pub struct FfiPluginHandle {
+    raw: FfiPlugin,
+    kind: FfiPluginKind,
+    name: String,
+}
+
+// The wrapped plugin was created from a `Plugins` value, which is `Send + Sync`.
+unsafe impl Send for FfiPluginHandle {}
+unsafe impl Sync for FfiPluginHandle {}
+
+impl FfiPluginHandle {
+    /// Takes ownership of `raw`, caching its kind and name. `raw` is dropped
+    /// when its kind is invalid.
+    ///
+    /// # Safety
+    ///
+    /// `raw` must come from a live library exporting the C ABI, and that
+    /// library must outlive the handle.
+    pub unsafe fn new(raw: FfiPlugin) -> Result<Self, Box<dyn std::error::Error>> {
+        let kind = match FfiPluginKind::try_from(raw.kind) {
+            Ok(kind) => kind,
+            Err(error) => {
+                unsafe { (raw.drop)(raw.instance) };
+                return Err(error.into());
+            }
+        };
+        let mut handle = FfiPluginHandle {
+            raw,
+            kind,
+            name: String::new(),
+        };
+        let result = unsafe { (handle.raw.name)(handle.raw.instance) };
+        handle.name = result_to_message("name", result)?;
+        Ok(handle)
+    }
+
+    pub fn kind(&self) -> FfiPluginKind {
+        self.kind
+    }
+
+    fn into_plugins(self) -> Plugins {
+        match self.kind {
+            FfiPluginKind::Base => Plugins::Base(Box::new(self)),
+            FfiPluginKind::Inventory => Plugins::Inventory(Box::new(self)),
+        }
+    }
+}
+
+impl Plugin for FfiPluginHandle {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn execute(&self, context: &dyn Any) -> Result<(), Box<dyn std::error::Error>> {
+        let bytes = encode_context(&self.name, context)?;
+        let result = unsafe { (self.raw.execute)(self.raw.instance, FfiStr::from_bytes(&bytes)) };
+        result_to_message(&self.name, result).map(|_| ())
+    }
+}
+
+impl PluginInventory for FfiPluginHandle {
+    fn load(&self) {
+        let result = unsafe { (self.raw.load)(self.raw.instance) };
+        if result.status == FFI_STATUS_PANIC {
+            // Re-raise on the host side so `Plugins::load` reports it.
+            panic::resume_unwind(Box::new(result.message.into_string()));
+        }
+        if let Err(error) = result_to_message(&self.name, result) {
+            log::error!("Inventory plugin '{}' failed to load: {error}", self.name);
+        }
+    }
+}
+
+impl Drop for FfiPluginHandle {
+    fn drop(&mut self) {
+        unsafe { (self.raw.drop)(self.raw.instance) };
+    }
+}
+
+fn result_to_message(
+    plugin: &str,
+    result: FfiResult,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let status = FfiStatus::try_from(result.status);
+    let message = result.message.into_string();
+    match status? {
+        FfiStatus::Ok => Ok(message),
+        FfiStatus::Error => Err(message.into()),
+        FfiStatus::Panic => Err(PluginError::PluginPanicked {
+            plugin: plugin.to_string(),
+            library: None,
+            message,
+        }
+        .into()),
+    }
+}
+
+/// Serializes an execution context for an FFI plugin.
+///
+/// Supported context types are `()`, `String`, `&'static str`, `Vec<u8>`,
+/// `&'static [u8]` and `serde_json::Value` (sent as JSON text).
+pub fn encode_context(plugin: &str, context: &dyn Any) -> Result<Vec<u8>, PluginError> {
+    if context.is::<()>() {
+        Ok(Vec::new())
+    } else if let Some(text) = context.downcast_ref::<String>() {
+        Ok(text.clone().into_bytes())
+    } else if let Some(text) = context.downcast_ref::<&'static str>() {
+        Ok(text.as_bytes().to_vec())
+    } else if let Some(bytes) = context.downcast_ref::<Vec<u8>>() {
+        Ok(bytes.clone())
+    } else if let Some(bytes) = context.downcast_ref::<&'static [u8]>() {
+        Ok(bytes.to_vec())
+    } else if let Some(value) = context.downcast_ref::<serde_json::Value>() {
+        Ok(value.to_string().into_bytes())
+    } else {
+        Err(PluginError::UnsupportedContext {
+            plugin: plugin.to_string(),
+        })
+    }
+}
+
+/// Returns true when `library` exports the C ABI descriptor.
+pub fn has_ffi_descriptor(library: &Library) -> bool {
+    unsafe { library.get::<*const FfiPluginDescriptor>(FFI_DESCRIPTOR_SYMBOL) }.is_ok()
+}
+
+/// Creates the plugins exported by an FFI plugin library.
+pub fn create_ffi_plugins(
+    library: &Library,
+    path: &Path,
+) -> Result<Vec<Plugins>, Box<dyn std::error::Error>> {
+    let descriptor = unsafe { library.get::<*const FfiPluginDescriptor>(FFI_DESCRIPTOR_SYMBOL)? };
+    let descriptor = unsafe { &**descriptor };
+    if descriptor.abi_version != FFI_ABI_VERSION {
+        return Err(PluginError::AbiMismatch {
+            path: path.to_path_buf(),
+            field: "C ABI version",
+            expected: FFI_ABI_VERSION.to_string(),
+            found: descriptor.abi_version.to_string(),
+        }
+        .into());
+    }
+
+    let mut list = unsafe { (descriptor.create_plugins)() };
+    let error = std::mem::replace(&mut list.error, FfiResult::ok(String::new()));
+    // Take the entries out of the library-owned buffer before freeing it.
+    let raw_plugins: Vec<FfiPlugin> = (0..list.len)
+        .map(|index| unsafe { std::ptr::read(list.ptr.add(index)) })
+        .collect();
+    unsafe { (list.free)(list.ptr, 0, list.capacity) };
+    result_to_message("create_plugins", error)
+        .map_err(|error| crate::plugin_errors::with_library(error, Some(path)))?;
+
+    // Wrap every entry first so all of them are released if one fails.
+    let mut plugins = Vec::with_capacity(raw_plugins.len());
+    let mut pending = raw_plugins.into_iter();
+    while let Some(raw) = pending.next() {
+        match unsafe { FfiPluginHandle::new(raw) } {
+            Ok(handle) => plugins.push(handle.into_plugins()),
+            Err(error) => {
+                for raw in pending {
+                    unsafe { (raw.drop)(raw.instance) };
+                }
+                return Err(crate::plugin_errors::with_library(error, Some(path)));
+            }
+        }
+    }
+    Ok(plugins)
+}
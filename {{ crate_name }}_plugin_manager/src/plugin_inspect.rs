@@ -0,0 +1,213 @@
+//! Pre-flight inspection of plugin libraries.
+//!
+//! `dlopen` reports a wrong-architecture library, a static archive or a stray
+//! text file with terse and platform-specific messages, and by the time it
+//! does the loader may already have run code from the file. Before opening a
+//! native library on an ELF platform, the manager parses its ELF header and
+//! dynamic symbol table and rejects it with a
+//! `PluginError::InvalidLibrary` naming the exact [`LibraryProblem`]. No code
+//! from the file is executed.
+
+use crate::plugin_errors::PluginError;
+use crate::plugin_ffi::FFI_DESCRIPTOR_SYMBOL;
+use crate::plugin_types::PluginSpec;
+use goblin::elf::{Elf, header, section_header, sym};
+use std::fmt;
+use std::path::Path;
+
+/// Whether the host loads ELF shared objects; other platforms skip inspection.
+pub(crate) const HOST_IS_ELF: bool = !cfg!(any(windows, target_vendor = "apple"));
+
+/// Why a file cannot be loaded as a plugin library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LibraryProblem {
+    /// A static `ar` archive (`.a`) rather than a shared object.
+    StaticArchive,
+    /// A text file, e.g. a linker script or a Git LFS pointer.
+    TextFile,
+    /// Neither ELF nor any other recognized format.
+    NotElf,
+    /// The ELF header or symbol tables could not be parsed.
+    Malformed(String),
+    /// An ELF file that is not a shared object, e.g. an executable or `.o`.
+    NotSharedObject {
+        kind: &'static str,
+    },
+    /// Built for another machine.
+    WrongArchitecture {
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// 32-bit library in a 64-bit host or the other way around.
+    WrongClass {
+        expected: u8,
+        found: u8,
+    },
+    WrongEndianness {
+        expected: &'static str,
+    },
+    /// The entry point is not among the exported dynamic symbols.
+    MissingEntryPoint {
+        symbol: String,
+    },
+}
+
+impl fmt::Display for LibraryProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LibraryProblem::StaticArchive => write!(
+                f,
+                "it is a static archive; build the plugin with crate-type = [\"cdylib\"]"
+            ),
+            LibraryProblem::TextFile => write!(f, "it is a text file, not a shared library"),
+            LibraryProblem::NotElf => write!(f, "it is not an ELF file"),
+            LibraryProblem::Malformed(message) => write!(f, "malformed ELF file: {message}"),
+            LibraryProblem::NotSharedObject { kind } => {
+                write!(f, "it is an ELF {kind}, not a shared object")
+            }
+            LibraryProblem::WrongArchitecture { expected, found } => {
+                write!(f, "it is built for {found}, but the host is {expected}")
+            }
+            LibraryProblem::WrongClass { expected, found } => write!(
+                f,
+                "it is a {found}-bit library, but the host is {expected}-bit"
+            ),
+            LibraryProblem::WrongEndianness { expected } => {
+                write!(f, "its byte order differs from the {expected} host")
+            }
+            LibraryProblem::MissingEntryPoint { symbol } => {
+                write!(f, "it does not export `{symbol}`")
+            }
+        }
+    }
+}
+
+/// Checks that `bytes`, the contents of the file at `path`, are a shared
+/// object for the host that exports the entry point of `spec`. Does nothing on
+/// non-ELF platforms.
+pub fn inspect_library(path: &Path, bytes: &[u8], spec: &PluginSpec) -> Result<(), PluginError> {
+    if !HOST_IS_ELF {
+        return Ok(());
+    }
+    check_elf(bytes, spec).map_err(|problem| PluginError::InvalidLibrary {
+        path: path.to_path_buf(),
+        problem,
+    })?;
+    log::debug!(
+        "Inspected {}: shared object exporting {}",
+        path.display(),
+        spec.entry_point()
+    );
+    Ok(())
+}
+
+/// The `DT_NEEDED` entries of `bytes`, the ELF library at `path`.
+pub fn needed_libraries(path: &Path, bytes: &[u8]) -> Result<Vec<String>, PluginError> {
+    let elf = Elf::parse(bytes).map_err(|error| PluginError::InvalidLibrary {
+        path: path.to_path_buf(),
+        problem: LibraryProblem::Malformed(error.to_string()),
+    })?;
+    Ok(elf.libraries.iter().map(|name| name.to_string()).collect())
+}
+
+/// Whether `prefix` is UTF-8, allowing a character cut off at its end.
+fn is_text(prefix: &[u8]) -> bool {
+    match std::str::from_utf8(prefix) {
+        Ok(_) => true,
+        Err(error) => error.error_len().is_none(),
+    }
+}
+
+fn check_elf(bytes: &[u8], spec: &PluginSpec) -> Result<(), LibraryProblem> {
+    if bytes.starts_with(b"!<arch>\n") {
+        return Err(LibraryProblem::StaticArchive);
+    }
+    // Too short to tell what it was meant to be, e.g. an interrupted copy.
+    if bytes.len() < header::header32::SIZEOF_EHDR {
+        return Err(LibraryProblem::Malformed(format!(
+            "{} bytes is shorter than an ELF header",
+            bytes.len()
+        )));
+    }
+    if !bytes.starts_with(header::ELFMAG) {
+        return Err(if is_text(&bytes[..bytes.len().min(512)]) {
+            LibraryProblem::TextFile
+        } else {
+            LibraryProblem::NotElf
+        });
+    }
+
+    // Check the identification bytes first: parsing a foreign class or byte
+    // order would only produce a less helpful error.
+    let class = match bytes.get(header::EI_CLASS) {
+        Some(&header::ELFCLASS32) => 32,
+        Some(&header::ELFCLASS64) => 64,
+        _ => return Err(LibraryProblem::Malformed("unknown ELF class".to_string())),
+    };
+    let host_class = usize::BITS as u8;
+    if class != host_class {
+        return Err(LibraryProblem::WrongClass {
+            expected: host_class,
+            found: class,
+        });
+    }
+    let (host_data, host_endian) = if cfg!(target_endian = "little") {
+        (header::ELFDATA2LSB, "little-endian")
+    } else {
+        (header::ELFDATA2MSB, "big-endian")
+    };
+    if bytes.get(header::EI_DATA) != Some(&host_data) {
+        return Err(LibraryProblem::WrongEndianness {
+            expected: host_endian,
+        });
+    }
+
+    let elf = Elf::parse(bytes).map_err(|error| LibraryProblem::Malformed(error.to_string()))?;
+    if elf.header.e_type != header::ET_DYN {
+        return Err(LibraryProblem::NotSharedObject {
+            kind: header::et_to_str(elf.header.e_type),
+        });
+    }
+    if let Some(expected) = host_machine()
+        && elf.header.e_machine != expected
+    {
+        return Err(LibraryProblem::WrongArchitecture {
+            expected: header::machine_to_str(expected),
+            found: header::machine_to_str(elf.header.e_machine),
+        });
+    }
+
+    let exports = |name: &[u8]| {
+        elf.dynsyms.iter().any(|symbol| {
+            symbol.st_shndx != section_header::SHN_UNDEF as usize
+                && matches!(symbol.st_bind(), sym::STB_GLOBAL | sym::STB_WEAK)
+                && elf.dynstrtab.get_at(symbol.st_name).map(str::as_bytes) == Some(name)
+        })
+    };
+    let entry_point = spec.entry_point();
+    let exported = exports(entry_point.as_bytes())
+        || (spec.is_default_entry_point() && exports(FFI_DESCRIPTOR_SYMBOL));
+    if !exported {
+        return Err(LibraryProblem::MissingEntryPoint {
+            symbol: entry_point.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// The `e_machine` of the host, or `None` when it is not known here.
+fn host_machine() -> Option<u16> {
+    Some(match std::env::consts::ARCH {
+        "x86" => header::EM_386,
+        "x86_64" => header::EM_X86_64,
+        "arm" => header::EM_ARM,
+        "aarch64" => header::EM_AARCH64,
+        "riscv32" | "riscv64" => header::EM_RISCV,
+        "powerpc" => header::EM_PPC,
+        "powerpc64" => header::EM_PPC64,
+        "s390x" => header::EM_S390,
+        "mips" | "mips64" => header::EM_MIPS,
+        "loongarch64" => header::EM_LOONGARCH,
+        _ => return None,
+    })
+}
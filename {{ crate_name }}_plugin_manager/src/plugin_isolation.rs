@@ -0,0 +1,503 @@
+//! Out-of-process plugin hosts.
+//!
+//! Catching panics does not help when a plugin segfaults or aborts. With
+//! [`PluginManager::with_isolation`] enabled, every library is loaded by a
+//! helper child process instead of the host. The child loads the library with
+//! `PluginManager::load_plugin` and serves `execute` and `load` calls over one
+//! end of a Unix socket pair it inherits from the host, so a crashing plugin only kills its helper and the call returns
+//! a `PluginError::HostCrashed`.
+//!
+//! The helper is the host's own executable by default; the host must call
+//! [`serve_if_requested`] at the very start of `main`:
+//!
+//! ```rust,no_run
+//! fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     plugin_manager::plugin_isolation::serve_if_requested();
+//!
+//!     let plugin_manager = plugin_manager::PluginManager::new()
+//!         .with_isolation(plugin_manager::plugin_isolation::IsolationConfig::new()?)
+//!         .activate_plugins()?;
+//!     plugin_manager.execute_plugin("plugin_a", &())?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Contexts are sent to the helper as bytes, exactly like for C ABI plugins
+//! (see `plugin_ffi::encode_context`); the plugin receives them as a `Vec<u8>`.
+
+use crate::PluginManager;
+use crate::plugin_errors::PluginError;
+use crate::plugin_ffi::encode_context;
+use crate::plugin_structs::{PluginDestroy, destroy_plugins, find_destroy_plugins};
+use crate::plugin_types::{Plugin, PluginInventory, PluginName, PluginSpec, Plugins};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Environment variable telling a helper process which inherited file
+/// descriptor is its socket, as `<fd>:<host pid>`.
+pub const HOST_SOCKET_ENV: &str = "PLUGIN_MANAGER_HOST_SOCKET";
+
+/// How long to wait for a helper process to answer a request by default.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How helper processes are started.
+#[derive(Debug, Clone)]
+pub struct IsolationConfig {
+    program: PathBuf,
+    args: Vec<String>,
+    restart: bool,
+    timeout: Duration,
+}
+
+impl IsolationConfig {
+    /// Runs the current executable as the helper.
+    pub fn new() -> io::Result<Self> {
+        Ok(Self::with_program(std::env::current_exe()?, Vec::new()))
+    }
+
+    /// Runs `program` with `args` as the helper; it must call
+    /// [`serve_if_requested`] on startup.
+    pub fn with_program(program: impl Into<PathBuf>, args: Vec<String>) -> Self {
+        IsolationConfig {
+            program: program.into(),
+            args,
+            restart: false,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Starts a new helper after a crash, so later calls can succeed again.
+    pub fn restart_on_crash(mut self, restart: bool) -> Self {
+        self.restart = restart;
+        self
+    }
+
+    /// How long to wait for the helper to answer a request before treating
+    /// it as crashed; defaults to 60 seconds.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl PluginManager {
+    /// Loads every library in a helper process, see [`IsolationConfig`].
+    pub fn with_isolation(mut self, config: IsolationConfig) -> Self {
+        self.isolation = Some(config);
+        self
+    }
+
+    /// Starts a helper process for `spec` and wraps the plugins it reports.
+    pub(crate) fn load_isolated(
+        &self,
+        config: &IsolationConfig,
+        spec: &PluginSpec,
+    ) -> Result<Vec<Plugins>, Box<dyn std::error::Error>> {
+        let (host, plugins) = HostProcess::spawn(config, spec)?;
+        let host = Arc::new(Mutex::new(host));
+        Ok(plugins
+            .into_iter()
+            .map(|(name, inventory)| {
+                let plugin = IsolatedPlugin {
+                    name,
+                    host: Arc::clone(&host),
+                };
+                if inventory {
+                    Plugins::Inventory(Box::new(plugin))
+                } else {
+                    Plugins::Base(Box::new(plugin))
+                }
+            })
+            .collect())
+    }
+}
+
+/// Plugin names reported by a helper, with `true` for inventory plugins.
+type HostedPlugins = Vec<(PluginName, bool)>;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Request {
+    Load {
+        spec: PluginSpec,
+    },
+    Execute {
+        plugin: PluginName,
+        context: Vec<u8>,
+    },
+    LoadInventory {
+        plugin: PluginName,
+    },
+    Unload {
+        plugin: PluginName,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum Response {
+    /// Plugin names, with `true` for inventory plugins.
+    Loaded {
+        plugins: Vec<(PluginName, bool)>,
+    },
+    Done,
+    Failed {
+        message: String,
+    },
+    Panicked {
+        plugin: PluginName,
+        message: String,
+    },
+}
+
+/// A running helper process and the connection to it.
+struct HostProcess {
+    config: IsolationConfig,
+    spec: PluginSpec,
+    library: PathBuf,
+    child: Child,
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl HostProcess {
+    fn spawn(
+        config: &IsolationConfig,
+        spec: &PluginSpec,
+    ) -> Result<(Self, HostedPlugins), Box<dyn std::error::Error>> {
+        let library = Path::new(&spec.path);
+        // A socket pair instead of a named socket, so no other process can
+        // connect in place of the helper.
+        let (stream, child_stream) = UnixStream::pair()?;
+        let child_fd = child_stream.as_raw_fd();
+        let mut command = Command::new(&config.program);
+        command.args(&config.args).env(
+            HOST_SOCKET_ENV,
+            format!("{child_fd}:{}", std::process::id()),
+        );
+        // SAFETY: `fcntl` is async-signal-safe and only touches the child's
+        // copy of the descriptor, which must survive `exec`.
+        unsafe {
+            command.pre_exec(move || {
+                if libc::fcntl(child_fd, libc::F_SETFD, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = command.spawn()?;
+        drop(child_stream);
+        log::debug!(
+            "Started plugin host {} for {}",
+            child.id(),
+            library.display()
+        );
+        stream.set_read_timeout(Some(config.timeout))?;
+
+        let mut host = HostProcess {
+            config: config.clone(),
+            spec: spec.clone(),
+            library: library.to_path_buf(),
+            child,
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+        match host.call(&Request::Load { spec: spec.clone() })? {
+            Response::Loaded { plugins } => Ok((host, plugins)),
+            Response::Failed { message } => Err(message.into()),
+            Response::Panicked { plugin, message } => Err(PluginError::PluginPanicked {
+                plugin,
+                library: Some(library.to_path_buf()),
+                message,
+            }
+            .into()),
+            Response::Done => Err("Unexpected response from plugin host".into()),
+        }
+    }
+
+    /// Sends a request and waits for the response, reporting a crash of the
+    /// helper as `PluginError::HostCrashed`.
+    fn call(&mut self, request: &Request) -> Result<Response, PluginError> {
+        let exchange = |host: &mut HostProcess| -> io::Result<Response> {
+            let mut line = serde_json::to_string(request)?;
+            line.push('\n');
+            host.writer.write_all(line.as_bytes())?;
+            let mut response = String::new();
+            if host.reader.read_line(&mut response)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            Ok(serde_json::from_str(&response)?)
+        };
+        exchange(self).map_err(|error| {
+            let timed_out = matches!(
+                error.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            );
+            // A no-op when the helper already died; otherwise it is unusable.
+            let _ = self.child.kill();
+            let status = match self.child.wait() {
+                Ok(_) if timed_out => format!(
+                    "no response within {:?}; does it call serve_if_requested()?",
+                    self.config.timeout
+                ),
+                Ok(status) => status.to_string(),
+                Err(_) => error.to_string(),
+            };
+            log::error!(
+                "Plugin host for {} crashed: {status}",
+                self.library.display()
+            );
+            PluginError::HostCrashed {
+                library: self.library.clone(),
+                status,
+            }
+        })
+    }
+
+    /// Replaces a crashed helper with a new one, when configured to.
+    fn restart(&mut self) {
+        if !self.config.restart {
+            return;
+        }
+        match HostProcess::spawn(&self.config, &self.spec) {
+            Ok((host, _)) => {
+                log::info!("Restarted plugin host for {}", self.library.display());
+                *self = host;
+            }
+            Err(error) => log::error!(
+                "Unable to restart plugin host for {}: {error}",
+                self.library.display()
+            ),
+        }
+    }
+
+    fn request(&mut self, request: Request) -> Result<(), Box<dyn std::error::Error>> {
+        match self.call(&request) {
+            Ok(Response::Done) => Ok(()),
+            Ok(Response::Failed { message }) => Err(message.into()),
+            Ok(Response::Panicked { plugin, message }) => Err(PluginError::PluginPanicked {
+                plugin,
+                library: Some(self.library.clone()),
+                message,
+            }
+            .into()),
+            Ok(Response::Loaded { .. }) => Err("Unexpected response from plugin host".into()),
+            Err(error) => {
+                self.restart();
+                Err(error.into())
+            }
+        }
+    }
+}
+
+impl Drop for HostProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// A plugin living in a helper process.
+pub struct IsolatedPlugin {
+    name: PluginName,
+    host: Arc<Mutex<HostProcess>>,
+}
+
+impl IsolatedPlugin {
+    /// Process id of the helper currently hosting the plugin.
+    pub fn host_id(&self) -> u32 {
+        self.lock().child.id()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HostProcess> {
+        self.host
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Plugin for IsolatedPlugin {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn execute(&self, context: &dyn Any) -> Result<(), Box<dyn std::error::Error>> {
+        let context = encode_context(&self.name, context)?;
+        self.lock().request(Request::Execute {
+            plugin: self.name.clone(),
+            context,
+        })
+    }
+
+    /// Unloads the plugin inside the helper, which runs its `on_unload`.
+    fn on_unload(&self) {
+        let result = self.lock().request(Request::Unload {
+            plugin: self.name.clone(),
+        });
+        if let Err(error) = result {
+            log::error!("Unable to unload isolated plugin '{}': {error}", self.name);
+        }
+    }
+}
+
+impl PluginInventory for IsolatedPlugin {
+    fn load(&self) {
+        let result = self.lock().request(Request::LoadInventory {
+            plugin: self.name.clone(),
+        });
+        match result.map_err(|error| error.downcast::<PluginError>()) {
+            Ok(()) => {}
+            // Re-raise on the host side so `Plugins::load` reports it.
+            Err(Ok(error)) if matches!(*error, PluginError::PluginPanicked { .. }) => {
+                if let PluginError::PluginPanicked { message, .. } = *error {
+                    std::panic::resume_unwind(Box::new(message));
+                }
+            }
+            Err(error) => {
+                let error = error.map_or_else(|error| error.to_string(), |error| error.to_string());
+                log::error!("Inventory plugin '{}' failed to load: {error}", self.name);
+            }
+        }
+    }
+}
+
+/// Turns the current process into a plugin host when it was started as one.
+///
+/// Returns immediately in a normal process; in a helper process it serves
+/// requests until the parent disconnects and then exits.
+pub fn serve_if_requested() {
+    let Some(stream) = inherited_stream() else {
+        return;
+    };
+    let code = match serve(stream) {
+        Ok(()) => 0,
+        Err(error) => {
+            eprintln!("Plugin host failed: {error}");
+            1
+        }
+    };
+    std::process::exit(code);
+}
+
+/// Takes over the socket the host passed down, if this process is a helper.
+fn inherited_stream() -> Option<UnixStream> {
+    let value = std::env::var(HOST_SOCKET_ENV).ok()?;
+    let (fd, host) = value.split_once(':')?;
+    let (fd, host) = (fd.parse::<i32>().ok()?, host.parse::<i32>().ok()?);
+    // Processes started by the helper inherit the variable but not the
+    // descriptor, so only the host's direct child may use it.
+    // SAFETY: `getppid` and `fcntl` have no preconditions.
+    if unsafe { libc::getppid() } != host
+        || unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1
+    {
+        return None;
+    }
+    // SAFETY: the host left this descriptor open for us and nothing else in
+    // this process owns it.
+    Some(unsafe { UnixStream::from_raw_fd(fd) })
+}
+
+fn serve(stream: UnixStream) -> Result<(), Box<dyn std::error::Error>> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    let manager = PluginManager::new();
+    // Declared before the plugins so the plugins are dropped first.
+    let mut _library = None;
+    let mut destroy = None;
+    let mut plugins: HashMap<PluginName, Plugins> = HashMap::new();
+    let mut order: Vec<PluginName> = Vec::new();
+
+    for line in reader.lines() {
+        let response = match serde_json::from_str::<Request>(&line?)? {
+            Request::Load { spec } => match manager.load_plugin_entry(&spec.path, &spec) {
+                Ok((library, loaded)) => match crate::name_plugins(loaded, Path::new(&spec.path)) {
+                    Ok(named) => {
+                        destroy = find_destroy_plugins(&library);
+                        _library = Some(library);
+                        let mut names = Vec::new();
+                        for (name, plugin) in named {
+                            names.push((name.clone(), matches!(plugin, Plugins::Inventory(_))));
+                            order.push(name.clone());
+                            plugins.insert(name, plugin);
+                        }
+                        Response::Loaded { plugins: names }
+                    }
+                    Err(error) => failure(error),
+                },
+                Err(error) => failure(error),
+            },
+            Request::Execute { plugin, context } => match plugins.get(&plugin) {
+                Some(loaded) => match loaded.execute(&context) {
+                    Ok(()) => Response::Done,
+                    Err(error) => failure(error),
+                },
+                None => Response::Failed {
+                    message: format!("Plugin '{plugin}' not found"),
+                },
+            },
+            Request::LoadInventory { plugin } => match plugins.get(&plugin) {
+                Some(loaded) => match loaded.load() {
+                    Ok(()) => Response::Done,
+                    Err(error) => failure(error.into()),
+                },
+                None => Response::Failed {
+                    message: format!("Plugin '{plugin}' not found"),
+                },
+            },
+            Request::Unload { plugin } => match plugins.remove(&plugin) {
+                Some(loaded) => {
+                    order.retain(|name| *name != plugin);
+                    unload(destroy, loaded)
+                }
+                None => Response::Failed {
+                    message: format!("Plugin '{plugin}' not found"),
+                },
+            },
+        };
+        let mut line = serde_json::to_string(&response)?;
+        line.push('\n');
+        writer.write_all(line.as_bytes())?;
+    }
+    // The host went away; unload what is left in reverse load order.
+    for name in order.iter().rev() {
+        if let Some(loaded) = plugins.remove(name) {
+            unload(destroy, loaded);
+        }
+    }
+    Ok(())
+}
+
+/// Runs `on_unload`, then destroys the plugin, like `PluginManager` does.
+fn unload(destroy: Option<PluginDestroy>, plugin: Plugins) -> Response {
+    let response = match plugin.on_unload() {
+        Ok(()) => Response::Done,
+        Err(error) => failure(error.into()),
+    };
+    destroy_plugins(destroy, vec![plugin]);
+    response
+}
+
+fn failure(error: Box<dyn std::error::Error>) -> Response {
+    match error.downcast::<PluginError>() {
+        Ok(error) => match *error {
+            PluginError::PluginPanicked {
+                plugin, message, ..
+            } => Response::Panicked { plugin, message },
+            error => Response::Failed {
+                message: error.to_string(),
+            },
+        },
+        Err(error) => Response::Failed {
+            message: error.to_string(),
+        },
+    }
+}
@@ -0,0 +1,378 @@
+//! Plugin metadata merged from several configuration layers.
+//!
+//! [`PluginManager::from_layers`] reads, from lowest to highest precedence:
+//!
+//! 1. System: `/etc/<app>/plugins.toml` (`%PROGRAMDATA%\<app>\plugins.toml`
+//!    on Windows).
+//! 2. User: `$XDG_CONFIG_HOME/<app>/plugins.toml`, by default
+//!    `~/.config/<app>/plugins.toml` (`%APPDATA%\<app>\plugins.toml` on
+//!    Windows).
+//! 3. Project: the manifest named by `CARGO_MANIFEST_PATH`, including its
+//!    workspace metadata.
+//! 4. Environment: variables named `<APP>_PLUGINS__<NAME>__<FIELD>`, e.g.
+//!    `MY_APP_PLUGINS__PLUGIN_A__ENABLED=false`.
+//!
+//! Missing files are skipped. Layers are merged with [`Metadata::merge`], so a
+//! higher layer replaces entries of the same name and adds members to groups.
+//!
+//! An environment variable sets one field of a detailed entry (or of a
+//! directory entry), turning an individual entry into a detailed one. Values
+//! are read as JSON when they parse, e.g. `true`, `10` or `["now"]`, and as a
+//! string otherwise. Placeholders in `PATH`, `DIR`, `LIBRARY_PATHS`, `INCLUDE`
+//! and `EXCLUDE` are expanded as in files, and relative paths are resolved
+//! against the working directory. `NAME` matches entry names
+//! case-insensitively, with `_` also matching `-`, and an unknown name adds an
+//! entry, which then needs a `PATH`. `ENABLED=false` also removes group and directory entries.
+//!
+//! [`PluginManager::get_entry_origin`] reports the layer each activated entry
+//! came from.
+
+use crate::plugin_config::{
+    ConfigFormat, ConfigSource, MANIFEST_PATH_VAR, expand_path, is_origin_relative, prepare_path,
+    read_config,
+};
+use crate::plugin_types::{GroupOrName, PluginEntry, PluginSpec};
+use crate::{Metadata, PluginManager};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// A configuration layer, ordered from lowest to highest precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ConfigLayer {
+    System,
+    User,
+    Project,
+    Env,
+}
+
+impl fmt::Display for ConfigLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ConfigLayer::System => "system",
+            ConfigLayer::User => "user",
+            ConfigLayer::Project => "project",
+            ConfigLayer::Env => "environment",
+        })
+    }
+}
+
+/// Where an effective entry was last set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigOrigin {
+    pub layer: ConfigLayer,
+    /// The file, or the environment variable.
+    pub source: String,
+}
+
+impl fmt::Display for ConfigOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} config {}", self.layer, self.source)
+    }
+}
+
+/// The layers read by [`ConfigSource::Layered`].
+#[derive(Debug, Clone)]
+pub struct LayeredConfig {
+    system_file: Option<PathBuf>,
+    user_file: Option<PathBuf>,
+    /// `None` reads the manifest named by `CARGO_MANIFEST_PATH`, if set.
+    project_file: Option<PathBuf>,
+    env_prefix: String,
+}
+
+impl LayeredConfig {
+    /// The default layers of the application `app`.
+    pub fn new(app: &str) -> Self {
+        let prefix: String = app
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        LayeredConfig {
+            system_file: system_dir().map(|dir| dir.join(app).join("plugins.toml")),
+            user_file: user_dir().map(|dir| dir.join(app).join("plugins.toml")),
+            project_file: None,
+            env_prefix: format!("{prefix}_PLUGINS__"),
+        }
+    }
+
+    pub fn with_system_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.system_file = Some(path.into());
+        self
+    }
+
+    pub fn with_user_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.user_file = Some(path.into());
+        self
+    }
+
+    /// A manifest or standalone file, in the format detected from its name.
+    pub fn with_project_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.project_file = Some(path.into());
+        self
+    }
+
+    /// The prefix of override variables, `<APP>_PLUGINS__` by default.
+    pub fn with_env_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.env_prefix = prefix.into();
+        self
+    }
+
+    pub fn system_file(&self) -> Option<&Path> {
+        self.system_file.as_deref()
+    }
+
+    pub fn user_file(&self) -> Option<&Path> {
+        self.user_file.as_deref()
+    }
+
+    pub fn env_prefix(&self) -> &str {
+        &self.env_prefix
+    }
+
+    /// Reads and merges every layer, returning the layer each entry was last
+    /// set by.
+    pub fn load(
+        &self,
+    ) -> Result<(Metadata, HashMap<GroupOrName, ConfigOrigin>), Box<dyn std::error::Error>> {
+        let variables = std::env::vars_os()
+            .filter_map(|(variable, value)| Some((variable.into_string().ok()?, value)))
+            .filter(|(variable, _)| variable.starts_with(&self.env_prefix))
+            .map(|(variable, value)| match value.into_string() {
+                Ok(value) => Ok((variable, value)),
+                Err(_) => Err(format!("{variable}: the value is not valid Unicode")),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.load_with_env(variables)
+    }
+
+    /// Like [`load`](Self::load), with the environment layer read from
+    /// `variables` instead of the process environment.
+    pub fn load_with_env(
+        &self,
+        variables: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<(Metadata, HashMap<GroupOrName, ConfigOrigin>), Box<dyn std::error::Error>> {
+        let project_file = self
+            .project_file
+            .clone()
+            .or_else(|| std::env::var_os(MANIFEST_PATH_VAR).map(PathBuf::from));
+        let files = [
+            (ConfigLayer::System, &self.system_file),
+            (ConfigLayer::User, &self.user_file),
+            (ConfigLayer::Project, &project_file),
+        ];
+        let mut metadata = Metadata::default();
+        let mut origins = HashMap::new();
+        for (layer, path) in files {
+            let Some(path) = path.as_deref().filter(|path| path.exists()) else {
+                log::debug!("No {layer} plugin config");
+                continue;
+            };
+            log::debug!("Reading {layer} plugin config {}", path.display());
+            let format = match layer {
+                ConfigLayer::Project if self.project_file.is_none() => ConfigFormat::Manifest,
+                _ => ConfigFormat::detect(path)?,
+            };
+            let layer_metadata = read_config(path, format)?;
+            let origin = ConfigOrigin {
+                layer,
+                source: path.display().to_string(),
+            };
+            for group_or_name in layer_metadata.plugins.iter().flat_map(HashMap::keys) {
+                origins.insert(group_or_name.clone(), origin.clone());
+            }
+            metadata = metadata.merge(layer_metadata);
+        }
+        self.apply_env(&mut metadata, &mut origins, variables)?;
+        Ok((metadata, origins))
+    }
+
+    /// Applies the override variables among `variables`, in the order of
+    /// their names.
+    fn apply_env(
+        &self,
+        metadata: &mut Metadata,
+        origins: &mut HashMap<GroupOrName, ConfigOrigin>,
+        variables: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut variables: Vec<(String, String)> = variables
+            .into_iter()
+            .filter(|(variable, _)| variable.starts_with(&self.env_prefix))
+            .collect();
+        if variables.is_empty() {
+            return Ok(());
+        }
+        variables.sort();
+        let base = std::env::current_dir()?;
+        let plugins = metadata.plugins.get_or_insert_with(HashMap::new);
+        for (variable, value) in variables {
+            let Some((name, field)) = variable[self.env_prefix.len()..].split_once("__") else {
+                return Err(
+                    format!("{variable}: expected {}<NAME>__<FIELD>", self.env_prefix).into(),
+                );
+            };
+            let group_or_name = plugins
+                .keys()
+                .find(|key| matches_env_name(key, name))
+                .cloned()
+                .unwrap_or_else(|| name.to_ascii_lowercase());
+            log::debug!("Plugin entry {group_or_name} overridden by {variable}");
+            apply_override(
+                plugins,
+                &group_or_name,
+                &field.to_ascii_lowercase(),
+                &value,
+                &base,
+            )
+            .map_err(|error| format!("{variable}: {error}"))?;
+            if !plugins.contains_key(&group_or_name) {
+                origins.remove(&group_or_name);
+                continue;
+            }
+            origins.insert(
+                group_or_name,
+                ConfigOrigin {
+                    layer: ConfigLayer::Env,
+                    source: variable,
+                },
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Whether the entry name `key` matches the `NAME` of a variable, which cannot
+/// contain `-`: case-insensitively, with `-` and `_` treated as equal.
+fn matches_env_name(key: &str, name: &str) -> bool {
+    let normalize = |byte: u8| match byte {
+        b'-' => b'_',
+        byte => byte.to_ascii_lowercase(),
+    };
+    key.len() == name.len()
+        && key
+            .bytes()
+            .zip(name.bytes())
+            .all(|(key, name)| normalize(key) == normalize(name))
+}
+
+/// Sets `field` of the entry `group_or_name` to `value`, with its paths
+/// relative to `base`.
+fn apply_override(
+    plugins: &mut HashMap<GroupOrName, PluginEntry>,
+    group_or_name: &str,
+    field: &str,
+    value: &str,
+    base: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut value = serde_json::from_str(value)
+        .unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+    prepare_value(base, group_or_name, field, &mut value)?;
+    let disable = field == "enabled" && value == false;
+    let updated = match plugins.get(group_or_name) {
+        Some(PluginEntry::Group(_) | PluginEntry::Directory(_)) if disable => {
+            plugins.remove(group_or_name);
+            return Ok(());
+        }
+        Some(PluginEntry::Group(_)) => return Err("group entries can only be disabled".into()),
+        Some(PluginEntry::Directory(spec)) => {
+            let mut table = to_table(spec)?;
+            table.insert(field.to_string(), value);
+            serde_json::from_value(table.into())
+                .map(PluginEntry::Directory)
+                .map_err(|error| format!("invalid directory entry: {error}"))?
+        }
+        entry => {
+            let mut table = match entry {
+                Some(PluginEntry::Individual(path)) => to_table(&PluginSpec::from_path(path))?,
+                Some(PluginEntry::Detailed(spec)) => to_table(spec)?,
+                _ => serde_json::Map::new(),
+            };
+            table.insert(field.to_string(), value);
+            serde_json::from_value(table.into())
+                .map(PluginEntry::Detailed)
+                .map_err(|error| format!("invalid plugin entry: {error}"))?
+        }
+    };
+    plugins.insert(group_or_name.to_string(), updated);
+    Ok(())
+}
+
+/// Expands and resolves the paths in an override value, the way the paths
+/// read from a file are.
+fn prepare_value(
+    base: &Path,
+    group_or_name: &str,
+    field: &str,
+    value: &mut serde_json::Value,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match (field, value) {
+        ("path" | "dir", serde_json::Value::String(path)) => {
+            prepare_path(base, group_or_name, path)?;
+        }
+        ("library_paths", serde_json::Value::Array(directories)) => {
+            for directory in directories {
+                if let serde_json::Value::String(directory) = directory
+                    && !is_origin_relative(directory)
+                {
+                    prepare_path(base, group_or_name, directory)?;
+                }
+            }
+        }
+        ("include" | "exclude", serde_json::Value::Array(patterns)) => {
+            for pattern in patterns {
+                if let serde_json::Value::String(pattern) = pattern {
+                    *pattern = expand_path(pattern)
+                        .map_err(|error| format!("Plugin entry {group_or_name}: {error}"))?;
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn to_table(
+    spec: &impl serde::Serialize,
+) -> Result<serde_json::Map<String, serde_json::Value>, Box<dyn std::error::Error>> {
+    match serde_json::to_value(spec)? {
+        serde_json::Value::Object(table) => Ok(table),
+        _ => Err("plugin entry is not a table".into()),
+    }
+}
+
+#[cfg(windows)]
+fn system_dir() -> Option<PathBuf> {
+    std::env::var_os("PROGRAMDATA").map(PathBuf::from)
+}
+
+#[cfg(not(windows))]
+fn system_dir() -> Option<PathBuf> {
+    Some(PathBuf::from("/etc"))
+}
+
+#[cfg(windows)]
+fn user_dir() -> Option<PathBuf> {
+    std::env::var_os("APPDATA").map(PathBuf::from)
+}
+
+#[cfg(not(windows))]
+fn user_dir() -> Option<PathBuf> {
+    match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if Path::new(&dir).is_absolute() => Some(PathBuf::from(dir)),
+        _ => std::env::home_dir().map(|home| home.join(".config")),
+    }
+}
+
+impl PluginManager {
+    /// A manager configured by the system, user, project and environment
+    /// layers of the application `app`, see [`plugin_layers`](crate::plugin_layers).
+    pub fn from_layers(app: &str) -> Self {
+        Self::new().with_config_source(ConfigSource::Layered(LayeredConfig::new(app)))
+    }
+}
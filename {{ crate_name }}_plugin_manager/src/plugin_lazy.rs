@@ -0,0 +1,425 @@
+//! Lazy activation of plugin libraries.
+//!
+//! With [`PluginManager::with_lazy_loading`] enabled, `activate_plugins` only
+//! records the entries of the plugin metadata. A library is opened the first
+//! time `get_plugin` or `execute_plugin` asks for a plugin it provides.
+//!
+//! Which library provides which plugin is read from a name index, keyed like
+//! the entries of `[package.metadata.plugins]`:
+//!
+//! ```toml
+//! [package.metadata.plugin_index]
+//! plugin_a_b = ["plugin_a", "plugin_b"]
+//! inventory_a = ["inventory_a"]
+//! ```
+//!
+//! or from a cache file written by a previous run, see
+//! [`PluginManager::with_lazy_loading_cache`]. Libraries missing from both are
+//! opened one at a time when a plugin that is not indexed is requested.
+//!
+//! Lazily loaded plugins move into `PluginManager::plugins` on the next call
+//! taking `&mut self`, e.g. `deregister_plugin` or `reload_changed`.
+
+use crate::PluginManager;
+use crate::plugin_structs::{FileStamp, LoadedLibrary};
+use crate::plugin_types::{GroupOrName, PluginEntry, PluginName, PluginSpec, Plugins};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::UNIX_EPOCH;
+
+/// Plugins created by a lazily opened library.
+struct LoadedPlugins {
+    // Declared first so the plugins are dropped before their library.
+    plugins: HashMap<PluginName, Plugins>,
+    library: LoadedLibrary,
+}
+
+/// A library listed in the metadata that may not have been opened yet.
+struct LazyLibrary {
+    spec: PluginSpec,
+    /// The plugins the library is known to provide, if indexed.
+    names: Option<Vec<PluginName>>,
+    /// Plugins deregistered before the library was opened.
+    deregistered: Vec<PluginName>,
+    loaded: OnceLock<Result<LoadedPlugins, String>>,
+}
+
+/// An entry of the index cache, valid while the library file is unchanged.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CachedLibrary {
+    len: u64,
+    modified_nanos: Option<u128>,
+    plugins: Vec<PluginName>,
+}
+
+impl CachedLibrary {
+    fn matches(&self, stamp: &FileStamp) -> bool {
+        self.len == stamp.len && self.modified_nanos == modified_nanos(stamp)
+    }
+}
+
+fn modified_nanos(stamp: &FileStamp) -> Option<u128> {
+    stamp
+        .modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_nanos())
+}
+
+/// The name index cache shared between runs.
+struct IndexCache {
+    path: PathBuf,
+    entries: Mutex<HashMap<String, CachedLibrary>>,
+}
+
+impl IndexCache {
+    fn open(path: PathBuf) -> Self {
+        let entries = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        IndexCache {
+            path,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    fn lookup(&self, spec: &PluginSpec) -> Option<Vec<PluginName>> {
+        let stamp = FileStamp::of(Path::new(&spec.path))?;
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries
+            .get(&cache_key(spec))
+            .filter(|cached| cached.matches(&stamp))
+            .map(|cached| cached.plugins.clone())
+    }
+
+    fn record(&self, spec: &PluginSpec, stamp: Option<FileStamp>, plugins: Vec<PluginName>) {
+        let Some(stamp) = stamp else {
+            return;
+        };
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.insert(
+            cache_key(spec),
+            CachedLibrary {
+                len: stamp.len,
+                modified_nanos: modified_nanos(&stamp),
+                plugins,
+            },
+        );
+    }
+
+    fn save(&self) {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let result = serde_json::to_string_pretty(&*entries)
+            .map_err(std::io::Error::other)
+            .and_then(|contents| std::fs::write(&self.path, contents));
+        if let Err(error) = result {
+            log::warn!(
+                "Unable to write plugin index cache {}: {error}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+/// Libraries are cached by path, plus the entry point when it is not the default.
+fn cache_key(spec: &PluginSpec) -> String {
+    match &spec.entry_point {
+        Some(entry_point) => format!("{}#{entry_point}", spec.path),
+        None => spec.path.clone(),
+    }
+}
+
+/// Specs of the libraries of a directory or glob entry, keyed by library name
+/// in the index.
+fn library_specs(libraries: Vec<PathBuf>) -> Vec<(GroupOrName, PluginSpec)> {
+    libraries
+        .into_iter()
+        .map(|library| {
+            let name = crate::plugin_discovery::library_name(&library);
+            (name, PluginSpec::from_path(library.to_string_lossy()))
+        })
+        .collect()
+}
+
+/// The libraries recorded by a lazy `activate_plugins`.
+pub(crate) struct LazyPlugins {
+    libraries: Vec<LazyLibrary>,
+    index: HashMap<PluginName, usize>,
+    cache: Option<IndexCache>,
+    /// Serializes loading so duplicate names are detected reliably.
+    load_lock: Mutex<()>,
+}
+
+impl LazyPlugins {
+    pub(crate) fn new(cache: Option<PathBuf>) -> Self {
+        LazyPlugins {
+            libraries: Vec::new(),
+            index: HashMap::new(),
+            cache: cache.map(IndexCache::open),
+            load_lock: Mutex::new(()),
+        }
+    }
+
+    /// Records a library, indexed by `names` or by the cache.
+    pub(crate) fn defer(&mut self, spec: PluginSpec, names: Option<Vec<PluginName>>) {
+        let names = names.or_else(|| self.cache.as_ref()?.lookup(&spec));
+        for name in names.iter().flatten() {
+            self.index
+                .entry(name.clone())
+                .or_insert(self.libraries.len());
+        }
+        self.libraries.push(LazyLibrary {
+            spec,
+            names,
+            deregistered: Vec::new(),
+            loaded: OnceLock::new(),
+        });
+    }
+
+    fn loaded(&self) -> impl Iterator<Item = &LoadedPlugins> {
+        self.libraries
+            .iter()
+            .filter_map(|library| library.loaded.get()?.as_ref().ok())
+    }
+
+    /// Names of the plugins that can be requested without opening an
+    /// unindexed library.
+    pub(crate) fn names(&self) -> Vec<&PluginName> {
+        let mut names: Vec<&PluginName> = self.index.keys().collect();
+        for loaded in self.loaded() {
+            names.extend(
+                loaded
+                    .plugins
+                    .keys()
+                    .filter(|n| !self.index.contains_key(*n)),
+            );
+        }
+        names
+    }
+
+    /// Plugins of the libraries opened so far.
+    pub(crate) fn loaded_plugins(&self) -> impl Iterator<Item = (&PluginName, &Plugins)> {
+        self.loaded().flat_map(|loaded| loaded.plugins.iter())
+    }
+
+    /// The path of the library a loaded plugin was created from.
+    pub(crate) fn library_of(&self, name: &str) -> Option<&Path> {
+        self.loaded()
+            .find(|loaded| loaded.plugins.contains_key(name))
+            .map(|loaded| loaded.library.path.as_path())
+    }
+
+    pub(crate) fn loaded_libraries(&self) -> impl Iterator<Item = &Path> {
+        self.loaded().map(|loaded| loaded.library.path.as_path())
+    }
+
+    /// Finds `name`, opening the library that provides it if needed.
+    pub(crate) fn get<'a>(
+        &'a self,
+        manager: &PluginManager,
+        name: &str,
+    ) -> Option<Result<&'a Plugins, String>> {
+        if let Some(&position) = self.index.get(name) {
+            return Some(self.load(manager, position).and_then(|loaded| {
+                loaded.plugins.get(name).ok_or_else(|| {
+                    format!("Plugin '{name}' is not provided by its indexed library")
+                })
+            }));
+        }
+        if let Some(plugin) = self.loaded().find_map(|loaded| loaded.plugins.get(name)) {
+            return Some(Ok(plugin));
+        }
+        for (position, library) in self.libraries.iter().enumerate() {
+            if library.names.is_none()
+                && library.loaded.get().is_none()
+                && let Ok(loaded) = self.load(manager, position)
+                && let Some(plugin) = loaded.plugins.get(name)
+            {
+                return Some(Ok(plugin));
+            }
+        }
+        None
+    }
+
+    fn load(&self, manager: &PluginManager, position: usize) -> Result<&LoadedPlugins, String> {
+        let library = &self.libraries[position];
+        library
+            .loaded
+            .get_or_init(|| {
+                let _guard = self.load_lock.lock().unwrap_or_else(|e| e.into_inner());
+                log::debug!("Lazily loading plugin library: {}", library.spec.path);
+                self.open(manager, library)
+                    .inspect_err(|error| log::error!("{error}"))
+            })
+            .as_ref()
+            .map_err(Clone::clone)
+    }
+
+    fn open(&self, manager: &PluginManager, lazy: &LazyLibrary) -> Result<LoadedPlugins, String> {
+        let spec = &lazy.spec;
+        let (library, plugins) = manager.load_library(spec).map_err(|e| e.to_string())?;
+        let named =
+            crate::name_plugins(plugins, &library.path).map_err(|error| error.to_string())?;
+        let mut plugins = HashMap::new();
+        for (name, plugin) in named {
+            if lazy.deregistered.contains(&name) {
+                crate::plugin_structs::destroy_plugins(library.destroy, vec![plugin]);
+                continue;
+            }
+            let taken = manager.plugins.contains_key(&name)
+                || self
+                    .loaded()
+                    .any(|loaded| loaded.plugins.contains_key(&name));
+            if taken || plugins.contains_key(&name) {
+                drop(plugin);
+                drop(plugins);
+                library.close();
+                return Err(format!("Plugin '{name}' already registered"));
+            }
+            plugins.insert(name, plugin);
+        }
+        if let Some(cache) = &self.cache {
+            let mut names: Vec<PluginName> = plugins.keys().cloned().collect();
+            names.sort();
+            cache.record(spec, library.stamp, names);
+        }
+        Ok(LoadedPlugins { library, plugins })
+    }
+
+    /// Opens every library that has not been opened yet.
+    pub(crate) fn load_all(&self, manager: &PluginManager) -> Result<(), String> {
+        (0..self.libraries.len()).try_for_each(|position| self.load(manager, position).map(|_| ()))
+    }
+
+    fn reindex(&mut self) {
+        self.index.clear();
+        for (position, library) in self.libraries.iter().enumerate() {
+            for name in library.names.iter().flatten() {
+                self.index.entry(name.clone()).or_insert(position);
+            }
+        }
+    }
+
+    /// Removes an indexed plugin whose library has not been opened yet,
+    /// without opening it. Returns false for any other plugin.
+    pub(crate) fn deregister(&mut self, name: &str) -> bool {
+        let Some(&position) = self.index.get(name) else {
+            return false;
+        };
+        let library = &mut self.libraries[position];
+        if library.loaded.get().is_some() {
+            return false;
+        }
+        if let Some(names) = &mut library.names {
+            names.retain(|indexed| indexed != name);
+        }
+        library.deregistered.push(name.to_string());
+        if library.names.as_ref().is_some_and(Vec::is_empty) {
+            self.libraries.remove(position);
+        }
+        self.reindex();
+        true
+    }
+
+    /// Forgets every library that has not been opened yet.
+    pub(crate) fn clear(&mut self) {
+        self.libraries
+            .retain(|library| library.loaded.get().is_some());
+        self.reindex();
+    }
+
+    pub(crate) fn save_cache(&self) {
+        if let Some(cache) = &self.cache {
+            cache.save();
+        }
+    }
+}
+
+impl PluginManager {
+    /// Defers opening libraries until one of their plugins is requested.
+    pub fn with_lazy_loading(mut self) -> Self {
+        self.lazy = Some(LazyPlugins::new(None));
+        self
+    }
+
+    /// Like [`PluginManager::with_lazy_loading`], caching which plugins each
+    /// library provides in the JSON file at `path`, so the next run can open
+    /// only the library it needs without an index in the metadata.
+    pub fn with_lazy_loading_cache(mut self, path: impl Into<PathBuf>) -> Self {
+        self.lazy = Some(LazyPlugins::new(Some(path.into())));
+        self
+    }
+
+    /// Opens every library still pending in lazy mode and registers its plugins.
+    pub fn load_lazy_plugins(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let result = match &self.lazy {
+            Some(lazy) => lazy.load_all(self),
+            None => Ok(()),
+        };
+        self.absorb_lazy_plugins();
+        Ok(result?)
+    }
+
+    /// Records the libraries of a plugin entry for lazy loading.
+    ///
+    /// WASM modules and isolated libraries are registered right away.
+    pub(crate) fn defer_entry(
+        &mut self,
+        group_or_name: &GroupOrName,
+        plugin_entry: &PluginEntry,
+        index: Option<&HashMap<GroupOrName, Vec<PluginName>>>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let specs: Vec<(GroupOrName, PluginSpec)> = match plugin_entry {
+            PluginEntry::Individual(path) => match crate::plugin_discovery::glob_libraries(path)? {
+                Some(libraries) => library_specs(libraries),
+                None => vec![(group_or_name.clone(), PluginSpec::from_path(path))],
+            },
+            PluginEntry::Detailed(spec) => vec![(group_or_name.clone(), spec.clone())],
+            PluginEntry::Group(group_plugins) => group_plugins
+                .iter()
+                .map(|(name, path)| (name.clone(), PluginSpec::from_path(path)))
+                .collect(),
+            PluginEntry::Directory(spec) => library_specs(spec.libraries()?),
+        };
+        #[cfg(unix)]
+        let isolated = self.isolation.is_some();
+        #[cfg(not(unix))]
+        let isolated = false;
+        for (key, spec) in specs {
+            match &mut self.lazy {
+                Some(lazy) if !isolated && !crate::is_wasm_path(&spec.path) => {
+                    log::debug!("Deferring plugin library: {key} {}", spec.path);
+                    lazy.defer(spec, index.and_then(|index| index.get(&key)).cloned());
+                }
+                _ => self.register_entry(&spec)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the plugins of lazily opened libraries into `plugins`.
+    pub(crate) fn absorb_lazy_plugins(&mut self) {
+        let Some(lazy) = &mut self.lazy else {
+            return;
+        };
+        lazy.save_cache();
+        let mut absorbed = Vec::new();
+        for mut library in std::mem::take(&mut lazy.libraries) {
+            match library.loaded.take() {
+                Some(Ok(loaded)) => absorbed.push(loaded),
+                // Libraries that failed to load are retried on the next request.
+                _ => lazy.libraries.push(library),
+            }
+        }
+        lazy.reindex();
+
+        for LoadedPlugins { library, plugins } in absorbed {
+            let plugins = plugins.into_values().collect();
+            if let Err(error) = self.register_library_plugins(library, plugins) {
+                log::error!("{error}");
+            }
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Hot reloading of plugin libraries.
+//!
+//! With [`PluginManager::with_hot_reload`] enabled, [`PluginManager::reload_changed`]
+//! compares every open library against the file it was loaded from (the paths
+//! of the `PluginEntry` entries and `with_path`). A changed library is loaded
+//! again, its plugins are swapped in a single step and the previous library is
+//! closed once its plugins have been dropped.
+//!
+//! Nothing watches the files in the background: libraries are only reloaded
+//! when the host calls `reload_changed`, typically once per main loop
+//! iteration.
+//!
+//! The dynamic loader caches libraries by path, so the new version is opened
+//! from a copy of the file in a directory private to the manager instead of
+//! the original path, or from the shadow cache when shadow copying is enabled.
+
+use crate::PluginManager;
+use crate::plugin_structs::{FileStamp, LibraryId, LoadedLibrary};
+use crate::plugin_types::{PluginName, PluginSpec};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+
+/// Describes the outcome of reloading one library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReloadEvent {
+    /// The library was reloaded and its plugins were swapped.
+    Reloaded {
+        path: PathBuf,
+        /// Plugins exported only by the new version.
+        added: Vec<PluginName>,
+        /// Plugins exported only by the previous version.
+        removed: Vec<PluginName>,
+        /// Plugins exported by both versions.
+        replaced: Vec<PluginName>,
+    },
+    /// The new version could not be loaded; the previous plugins stay registered.
+    Failed { path: PathBuf, error: String },
+}
+
+impl PluginManager {
+    /// Enables hot reload mode, see [`PluginManager::reload_changed`].
+    ///
+    /// Changes are not watched for: libraries are only reloaded, and
+    /// [`ReloadEvent`]s only emitted, when the host polls `reload_changed`.
+    pub fn with_hot_reload(mut self) -> Self {
+        self.hot_reload = true;
+        self
+    }
+
+    /// Returns a receiver for every [`ReloadEvent`] emitted from now on.
+    pub fn subscribe_reload_events(&mut self) -> Receiver<ReloadEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.reload_subscribers.push(sender);
+        receiver
+    }
+
+    /// Reloads every library whose file changed on disk since it was loaded.
+    ///
+    /// Meant to be called periodically by the host, e.g. once per main loop
+    /// iteration. Does nothing unless hot reload mode is enabled.
+    pub fn reload_changed(&mut self) -> Vec<ReloadEvent> {
+        if !self.hot_reload {
+            return Vec::new();
+        }
+        self.absorb_lazy_plugins();
+
+        let mut changed: Vec<(LibraryId, FileStamp)> = self
+            .libraries
+            .iter()
+            .filter_map(|(id, loaded)| match FileStamp::of(&loaded.path) {
+                Some(stamp) if Some(stamp) != loaded.stamp => Some((*id, stamp)),
+                _ => None,
+            })
+            .collect();
+        changed.sort_by_key(|(id, _)| *id);
+
+        let mut events = Vec::new();
+        for (id, stamp) in changed {
+            let Some(loaded) = self.libraries.get_mut(&id) else {
+                continue;
+            };
+            // Record the new state up front so a broken build is only retried
+            // after the next change.
+            loaded.stamp = Some(stamp);
+            let path = loaded.path.clone();
+
+            let event = match self.swap_library(id, &path, stamp) {
+                Ok(event) => {
+                    log::info!("Reloaded plugin library: {}", path.display());
+                    event
+                }
+                Err(error) => {
+                    log::error!(
+                        "Failed to reload plugin library {}: {error}",
+                        path.display()
+                    );
+                    ReloadEvent::Failed {
+                        path,
+                        error: error.to_string(),
+                    }
+                }
+            };
+            self.reload_subscribers
+                .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+            events.push(event);
+        }
+        events
+    }
+
+    fn swap_library(
+        &mut self,
+        id: LibraryId,
+        path: &Path,
+        stamp: FileStamp,
+    ) -> Result<ReloadEvent, Box<dyn std::error::Error>> {
+        let spec = match self.libraries.get(&id) {
+            Some(loaded) => loaded.spec.clone(),
+            None => PluginSpec::from_path(path.to_string_lossy()),
+        };
+        let (mut loaded, plugins) = if self.shadow_cache.is_some() {
+            self.load_library(&spec)?
+        } else {
+            let copy = stage_copy(&self.reload_dir, path, self.next_library_id)?;
+            match self.load_plugin_entry(&copy.to_string_lossy(), &spec) {
+                Ok((library, plugins)) => {
+                    let mut loaded = LoadedLibrary::with_spec(library, path.to_path_buf(), spec);
+                    loaded.staged_copy = Some(copy);
+                    (loaded, plugins)
+                }
+                Err(error) => {
+                    let _ = std::fs::remove_file(&copy);
+                    return Err(error);
+                }
+            }
+        };
+        loaded.stamp = Some(stamp);
+
+        let old_names: HashSet<PluginName> = self
+            .plugin_libraries
+            .iter()
+            .filter(|(_, owner)| **owner == id)
+            .map(|(name, _)| name.clone())
+            .collect();
+        let named = match crate::name_plugins(plugins, path) {
+            Ok(named) => named,
+            Err(error) => {
+                loaded.close();
+                return Err(error);
+            }
+        };
+        let mut new_names = HashSet::new();
+        for (name, _) in &named {
+            let taken = self.plugins.contains_key(name) && !old_names.contains(name);
+            if taken || !new_names.insert(name.clone()) {
+                let msg = format!("Plugin '{name}' already registered");
+                drop(named);
+                loaded.close();
+                return Err(msg.into());
+            }
+        }
+
+        // Unload the previous plugins before their library, then register the new ones.
+        let mut old_sorted: Vec<&PluginName> = old_names.iter().collect();
+        old_sorted.sort();
+        for name in old_sorted {
+            if let Some(plugin) = self.plugins.remove(name) {
+                self.unload_plugin(name, plugin);
+            }
+        }
+        if let Some(old) = self.libraries.get_mut(&id) {
+            old.refcount = 0;
+        }
+        self.release_library(id);
+
+        let new_id = self.insert_library(loaded);
+        for (name, plugin) in named {
+            self.plugins.insert(name.clone(), plugin);
+            self.registration_order.push(name.clone());
+            self.plugin_libraries.insert(name, new_id);
+            if let Some(loaded) = self.libraries.get_mut(&new_id) {
+                loaded.refcount += 1;
+            }
+        }
+        self.release_library(new_id);
+
+        let sorted = |names: Vec<&PluginName>| {
+            let mut names: Vec<PluginName> = names.into_iter().cloned().collect();
+            names.sort();
+            names
+        };
+        Ok(ReloadEvent::Reloaded {
+            path: path.to_path_buf(),
+            added: sorted(new_names.difference(&old_names).collect()),
+            removed: sorted(old_names.difference(&new_names).collect()),
+            replaced: sorted(new_names.intersection(&old_names).collect()),
+        })
+    }
+}
+
+/// Copies `path` into the manager's staging `directory` under a name unique
+/// to `id`.
+fn stage_copy(directory: &Path, path: &Path, id: LibraryId) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(directory)?;
+    let file_name = path
+        .file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy();
+    let copy = directory.join(format!("{id}-{file_name}"));
+    std::fs::copy(path, &copy)?;
+    Ok(copy)
+}
@@ -0,0 +1,128 @@
+//! Shadow copies of plugin libraries.
+//!
+//! Opening a library straight from its build-output path means that
+//! `cargo build` overwriting the file can crash the running host. With
+//! [`PluginManager::with_shadow_copy`] enabled, every library is first copied
+//! into a private cache directory, named after the SHA-256 digest of its
+//! contents, and the copy is opened instead. The copies the manager made are
+//! removed when it is dropped.
+
+use crate::PluginManager;
+use crate::plugin_signature::encode_hex;
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// A content-addressed directory of library copies.
+#[derive(Debug)]
+pub struct ShadowCache {
+    directory: PathBuf,
+    /// Whether the directory is private to this cache and removed on cleanup.
+    private: bool,
+    /// The copies made by this cache, the only files cleanup removes.
+    staged: Mutex<Vec<PathBuf>>,
+}
+
+impl ShadowCache {
+    /// A cache in a directory under the system temp directory, private to
+    /// this cache.
+    pub fn private() -> Self {
+        ShadowCache {
+            directory: crate::private_temp_dir("shadow"),
+            private: true,
+            staged: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// A cache in a caller-provided directory.
+    pub fn in_dir(directory: impl Into<PathBuf>) -> Self {
+        ShadowCache {
+            directory: directory.into(),
+            private: false,
+            staged: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Copies `path` into the cache and returns the path of the copy.
+    ///
+    /// Identical contents map to the same copy, which is reused when present.
+    pub fn stage(&self, path: &Path) -> io::Result<PathBuf> {
+        let digest = sha256_file(path)?;
+        let file_name = path
+            .file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy();
+        let copy = self.directory.join(format!("{digest}-{file_name}"));
+        if !copy.exists() {
+            std::fs::create_dir_all(&self.directory)?;
+            // Copy under a temporary name first so a crash never leaves a
+            // truncated file behind the content-addressed name.
+            let partial = self
+                .directory
+                .join(format!(".{digest}-{}.partial", std::process::id()));
+            std::fs::copy(path, &partial)?;
+            std::fs::rename(&partial, &copy)?;
+            log::debug!("Shadow copied {} to {}", path.display(), copy.display());
+            self.staged
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .push(copy.clone());
+        }
+        Ok(copy)
+    }
+
+    /// Removes the copies this cache made; other files in the directory are
+    /// left alone. Must only be called once the libraries opened from it have
+    /// been closed.
+    pub fn cleanup(&self) {
+        let staged = std::mem::take(
+            &mut *self
+                .staged
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+        );
+        for path in staged {
+            if let Err(error) = std::fs::remove_file(&path) {
+                log::warn!("Unable to remove shadow copy {}: {error}", path.display());
+            }
+        }
+        if self.private {
+            let _ = std::fs::remove_dir(&self.directory);
+        }
+    }
+}
+
+impl PluginManager {
+    /// Loads every library from a shadow copy in a private temp directory.
+    pub fn with_shadow_copy(mut self) -> Self {
+        self.shadow_cache = Some(ShadowCache::private());
+        self
+    }
+
+    /// Loads every library from a shadow copy in `directory`.
+    ///
+    /// Copies already in `directory` are reused. Only the copies this manager
+    /// made are removed when it is dropped.
+    pub fn with_shadow_copy_dir(mut self, directory: impl Into<PathBuf>) -> Self {
+        self.shadow_cache = Some(ShadowCache::in_dir(directory));
+        self
+    }
+}
+
+/// Hex encoded SHA-256 digest of the file at `path`.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(encode_hex(&hasher.finalize()))
+}
+
+/// Hex encoded SHA-256 digest of `bytes`.
+pub fn sha256_bytes(bytes: &[u8]) -> String {
+    encode_hex(&Sha256::digest(bytes))
+}
@@ -0,0 +1,143 @@
+//! Ed25519 signatures for plugin libraries.
+//!
+//! With a [`SignaturePolicy`] other than `Off`, every native or WASM library
+//! must come with a detached signature next to it, `libfoo.so.sig`, made by
+//! one of the manager's trusted keys. The signature is checked before the
+//! library is opened:
+//!
+//! ```rust,no_run
+//! use plugin_manager::PluginManager;
+//! use plugin_manager::plugin_signature::{SignaturePolicy, public_key_from_hex};
+//!
+//! # fn doc_test() -> Result<(), Box<dyn std::error::Error>> {
+//! let key = public_key_from_hex(&std::fs::read_to_string("/etc/my_app/plugins.pub")?)?;
+//! let plugin_manager = PluginManager::new()
+//!     .with_trusted_keys([key])
+//!     .with_signature_policy(SignaturePolicy::Enforce)
+//!     .activate_plugins()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Keys and signatures are stored as hex text. The `plugin_signer` command in
+//! the `plugin_signer/` crate at the repository root generates key pairs and
+//! signs built libraries.
+
+use crate::PluginManager;
+use crate::plugin_errors::PluginError;
+use ed25519_dalek::{Signature, Verifier};
+use std::path::{Path, PathBuf};
+
+pub use ed25519_dalek::VerifyingKey;
+
+/// What happens when a library's signature is missing or invalid.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SignaturePolicy {
+    /// The library is not loaded.
+    Enforce,
+    /// A warning is logged and the library is loaded anyway.
+    Warn,
+    /// Signatures are not checked.
+    #[default]
+    Off,
+}
+
+impl PluginManager {
+    /// Adds public keys whose signatures are accepted.
+    pub fn with_trusted_keys(mut self, keys: impl IntoIterator<Item = VerifyingKey>) -> Self {
+        self.trusted_keys.extend(keys);
+        self
+    }
+
+    pub fn with_signature_policy(mut self, policy: SignaturePolicy) -> Self {
+        self.signature_policy = policy;
+        self
+    }
+
+    /// Checks the signature of `configured`, the path from the plugin entry,
+    /// against `contents`, which may have been read from a shadow copy of it.
+    pub(crate) fn verify_signature(
+        &self,
+        contents: &[u8],
+        configured: &Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.signature_policy == SignaturePolicy::Off {
+            return Ok(());
+        }
+        match check_signature(contents, configured, &self.trusted_keys) {
+            Ok(()) => {
+                log::debug!("Verified signature of {}", configured.display());
+                Ok(())
+            }
+            Err(reason) => {
+                let error = PluginError::SignatureRejected {
+                    path: configured.to_path_buf(),
+                    reason,
+                };
+                if self.signature_policy == SignaturePolicy::Enforce {
+                    return Err(error.into());
+                }
+                log::warn!("{error}; loading it anyway");
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The detached signature file for `library`: its path with `.sig` appended.
+pub fn signature_path(library: &Path) -> PathBuf {
+    let mut path = library.as_os_str().to_owned();
+    path.push(".sig");
+    PathBuf::from(path)
+}
+
+/// Parses a hex encoded Ed25519 public key.
+pub fn public_key_from_hex(hex: &str) -> Result<VerifyingKey, Box<dyn std::error::Error>> {
+    let bytes: [u8; 32] = decode_hex(hex.trim())
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or("Ed25519 public key must be 64 hex digits")?;
+    Ok(VerifyingKey::from_bytes(&bytes)?)
+}
+
+/// Checks the detached signature of `configured` against `contents`, the
+/// library's bytes, returning why it was rejected.
+pub fn check_signature(
+    contents: &[u8],
+    configured: &Path,
+    keys: &[VerifyingKey],
+) -> Result<(), String> {
+    let sig_path = signature_path(configured);
+    let text = std::fs::read_to_string(&sig_path)
+        .map_err(|error| format!("cannot read {}: {error}", sig_path.display()))?;
+    let bytes: [u8; 64] = decode_hex(text.trim())
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| format!("{} is not a hex encoded signature", sig_path.display()))?;
+    let signature = Signature::from_bytes(&bytes);
+    if keys.is_empty() {
+        return Err("no trusted keys are configured".to_string());
+    }
+    if keys
+        .iter()
+        .any(|key| key.verify(contents, &signature).is_ok())
+    {
+        Ok(())
+    } else {
+        Err("the signature does not match any trusted key".to_string())
+    }
+}
+
+/// Lowercase hex text of `bytes`.
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// The bytes of hex text, or `None` if it is not an even number of hex digits.
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,74 @@
+//! Plugins linked directly into the host binary.
+//!
+//! Deployments that must ship as a single static binary can still use the
+//! `Plugin` traits: a plugin crate registers its `create_plugins` function
+//! with [`register_static_plugins!`](crate::register_static_plugins) and the
+//! host depends on it as a regular Rust crate. `activate_plugins` then
+//! registers these plugins alongside the dynamically loaded ones. A static
+//! plugin whose name is already registered is a duplicate, exactly as if it
+//! had been loaded from a library.
+
+use crate::PluginManager;
+use crate::plugin_errors::catch_panic;
+use crate::plugin_types::{GroupOrName, Plugins};
+
+/// A `create_plugins` function registered at compile time.
+pub struct StaticPlugins {
+    /// The group or plugin name, as the key of a `PluginEntry` would be.
+    pub group_or_name: &'static str,
+    pub create: fn() -> Vec<Plugins>,
+}
+
+inventory::collect!(StaticPlugins);
+
+/// Registers a `create_plugins` function to be linked into the host.
+///
+/// ```rust
+/// use plugin_manager::plugin_types::Plugins;
+///
+/// pub fn create_plugins() -> Vec<Plugins> {
+///     Vec::new()
+/// }
+///
+/// // Keyed by the crate name, like an individual plugin entry...
+/// plugin_manager::register_static_plugins!(create_plugins);
+/// // ...or registered under a group.
+/// plugin_manager::register_static_plugins!(group = "tasks", create_plugins);
+/// ```
+#[macro_export]
+macro_rules! register_static_plugins {
+    ($create:path) => {
+        $crate::register_static_plugins!(group = env!("CARGO_PKG_NAME"), $create);
+    };
+    (group = $group:expr, $create:path) => {
+        $crate::inventory::submit! {
+            $crate::plugin_static::StaticPlugins {
+                group_or_name: $group,
+                create: $create,
+            }
+        }
+    };
+}
+
+/// Every registration linked into the binary, ordered by group or name.
+pub fn static_registrations() -> Vec<&'static StaticPlugins> {
+    let mut registrations: Vec<_> = inventory::iter::<StaticPlugins>.into_iter().collect();
+    registrations.sort_by_key(|registration| registration.group_or_name);
+    registrations
+}
+
+impl PluginManager {
+    /// Registers the plugins created by a static registration.
+    pub(crate) fn register_static(
+        &mut self,
+        group_or_name: GroupOrName,
+        create: fn() -> Vec<Plugins>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        log::debug!("Loading static plugins: {group_or_name}");
+        for plugin in catch_panic("create_plugins", create)? {
+            let name = plugin.try_name()?;
+            self.insert_plugin(name, plugin);
+        }
+        Ok(())
+    }
+}
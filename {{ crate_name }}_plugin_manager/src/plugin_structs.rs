@@ -1,11 +1,112 @@
-use crate::plugin_types::{Plugin, PluginInventory, Plugins};
+use crate::plugin_types::{Plugin, PluginInventory, PluginSpec, Plugins};
 use libloading::Library;
 use std::any::Any;
 use std::collections::{HashMap, hash_map};
 use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 pub type PluginCreate = unsafe fn() -> Vec<Plugins>;
+pub type PluginFactory = unsafe fn() -> Box<dyn Plugin>;
+pub type PluginDestroy = unsafe fn(Vec<Plugins>);
+
+/// Optional export taking back ownership of the library's plugins.
+pub const DESTROY_PLUGINS_SYMBOL: &[u8] = b"destroy_plugins";
 pub type PluginResult = Result<(Library, Vec<Plugins>), Box<dyn std::error::Error>>;
+pub type LibraryId = u64;
+
+/// A shared library owned by the `PluginManager`.
+///
+/// `refcount` is the number of registered plugins created from the library;
+/// the library is closed once it drops to zero.
+pub struct LoadedLibrary {
+    pub library: Library,
+    pub path: PathBuf,
+    /// The entry the library was loaded for, reused when reloading it.
+    pub spec: PluginSpec,
+    pub refcount: usize,
+    /// State of `path` when the library was loaded, used to detect rebuilds.
+    pub stamp: Option<FileStamp>,
+    /// Private copy the library was actually opened from, removed on close.
+    pub staged_copy: Option<PathBuf>,
+    /// The library's `destroy_plugins` export, if any.
+    pub destroy: Option<PluginDestroy>,
+}
+
+impl LoadedLibrary {
+    pub fn new(library: Library, path: PathBuf) -> Self {
+        let spec = PluginSpec::from_path(path.to_string_lossy());
+        Self::with_spec(library, path, spec)
+    }
+
+    pub fn with_spec(library: Library, path: PathBuf, spec: PluginSpec) -> Self {
+        LoadedLibrary {
+            stamp: FileStamp::of(&path),
+            destroy: find_destroy_plugins(&library),
+            library,
+            path,
+            spec,
+            refcount: 0,
+            staged_copy: None,
+        }
+    }
+
+    /// Closes the library, then removes the copy it was opened from.
+    pub fn close(self) {
+        log::info!("Closing plugin library: {}", self.path.display());
+        drop(self.library);
+        if let Some(copy) = self.staged_copy
+            && let Err(error) = std::fs::remove_file(&copy)
+        {
+            log::warn!("Unable to remove {}: {error}", copy.display());
+        }
+    }
+}
+
+/// Looks up `destroy_plugins` in a Rust ABI plugin library.
+pub fn find_destroy_plugins(library: &Library) -> Option<PluginDestroy> {
+    if crate::plugin_ffi::has_ffi_descriptor(library) {
+        return None;
+    }
+    let symbol = unsafe { library.get::<PluginDestroy>(DESTROY_PLUGINS_SYMBOL) }.ok()?;
+    Some(*symbol)
+}
+
+/// Hands `plugins` to `destroy` when the library exports it, otherwise drops
+/// them in the host.
+pub fn destroy_plugins(destroy: Option<PluginDestroy>, plugins: Vec<Plugins>) {
+    match destroy {
+        Some(destroy) => {
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| unsafe {
+                destroy(plugins)
+            }));
+            if let Err(payload) = result {
+                log::error!(
+                    "destroy_plugins panicked: {}",
+                    crate::plugin_errors::panic_message(payload.as_ref())
+                );
+            }
+        }
+        None => drop(plugins),
+    }
+}
+
+/// Modification time and size of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    pub modified: Option<SystemTime>,
+    pub len: u64,
+}
+
+impl FileStamp {
+    pub fn of(path: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        Some(FileStamp {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        })
+    }
+}
 
 pub struct InventoryPlugins {
     plugins: HashMap<String, Box<dyn PluginInventory>>,
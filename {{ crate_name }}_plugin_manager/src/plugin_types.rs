@@ -1,5 +1,7 @@
+use crate::plugin_dlopen::LoadFlag;
+use crate::plugin_errors::{PluginError, catch_panic};
 use libloading::Library;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::any::Any;
 use std::collections::HashMap;
 use std::fmt;
@@ -11,11 +13,177 @@ pub type PluginName = String;
 pub type PluginResult = Result<(Library, Vec<Box<dyn Plugin>>), Box<dyn std::error::Error>>;
 pub type PluginCreate = unsafe fn() -> Vec<Box<dyn Plugin>>;
 
-#[derive(Deserialize, Debug, Clone)]
-#[serde(untagged)]
+/// A value of `[package.metadata.plugins]`.
+///
+/// Strings are `Individual` entries. A table with a `path` key is a `Detailed` entry,
+/// a table with a `dir` key a `Directory` entry and any other table a `Group`
+/// of name = path pairs, so a group cannot contain a plugin named `path` or
+/// `dir`.
+#[derive(Debug, Clone)]
 pub enum PluginEntry {
+    /// A library path, or a glob such as `extras = "plugins/*.so"` when it
+    /// contains a wildcard and names no existing file, see `plugin_discovery`.
     Individual(PathString),
+    /// A table with a `path` key, e.g.
+    /// `plugin_a = { path = "libplugin_mods.so", entry_point = "create_plugin", mode = "single" }`.
+    Detailed(PluginSpec),
     Group(HashMap<String, PathString>),
+    /// A table with a `dir` key, see `plugin_discovery`.
+    Directory(DirectorySpec),
+}
+
+impl PluginEntry {
+    /// The spec of a detailed entry, which may disable or order it.
+    pub fn spec(&self) -> Option<&PluginSpec> {
+        match self {
+            PluginEntry::Detailed(spec) => Some(spec),
+            _ => None,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.spec().is_none_or(|spec| spec.enabled)
+    }
+
+    pub fn priority(&self) -> i32 {
+        self.spec().map_or(0, |spec| spec.priority)
+    }
+}
+
+impl<'de> Deserialize<'de> for PluginEntry {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        match serde_json::Value::deserialize(deserializer)? {
+            serde_json::Value::String(path) => Ok(PluginEntry::Individual(path)),
+            serde_json::Value::Object(table) if table.contains_key("path") => {
+                serde_json::from_value(serde_json::Value::Object(table))
+                    .map(PluginEntry::Detailed)
+                    .map_err(|error| D::Error::custom(format!("invalid plugin entry: {error}")))
+            }
+            serde_json::Value::Object(table) if table.contains_key("dir") => {
+                serde_json::from_value(serde_json::Value::Object(table))
+                    .map(PluginEntry::Directory)
+                    .map_err(|error| D::Error::custom(format!("invalid plugin entry: {error}")))
+            }
+            serde_json::Value::Object(table) => table
+                .into_iter()
+                .map(|(name, path)| match path {
+                    serde_json::Value::String(path) => Ok((name, path)),
+                    _ => Err(D::Error::custom(format!(
+                        "group member `{name}` must be a path string; detailed entries need a `path` key and directory entries a `dir` key"
+                    ))),
+                })
+                .collect::<Result<_, _>>()
+                .map(PluginEntry::Group),
+            _ => Err(D::Error::custom(
+                "a plugin entry must be a path string or a table",
+            )),
+        }
+    }
+}
+
+/// How a library is loaded and which symbol creates its plugins.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct PluginSpec {
+    pub path: PathString,
+    /// The exported factory; `create_plugins` when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entry_point: Option<String>,
+    #[serde(default)]
+    pub mode: EntryMode,
+    /// `dlopen` flags; `Library::new`'s defaults when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub flags: Vec<LoadFlag>,
+    /// Hex encoded SHA-256 digest the library must match before it is opened.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Directories searched for the library's own dependencies; `$ORIGIN`
+    /// is the library's directory. See `plugin_deps`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub library_paths: Vec<PathString>,
+    /// Disabled entries are skipped by `activate_plugins`.
+    #[serde(default = "enabled_default", skip_serializing_if = "is_true")]
+    pub enabled: bool,
+    /// Entries with a higher priority are activated first; the default is 0.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub priority: i32,
+    /// Host-defined settings, see `PluginManager::get_entry_config`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<serde_json::Value>,
+    /// Requirement on the plugin crate version recorded in its ABI descriptor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<semver::VersionReq>,
+}
+
+/// The libraries in a directory, see `plugin_discovery`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct DirectorySpec {
+    pub dir: PathString,
+    /// Also searches the subdirectories of `dir`.
+    #[serde(default)]
+    pub recursive: bool,
+    /// Patterns a file must match, in addition to having a library suffix.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Patterns of files to skip.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// Built from a glob string, whose pattern alone selects the files.
+    #[serde(skip)]
+    pub(crate) glob: bool,
+}
+
+fn enabled_default() -> bool {
+    true
+}
+
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+fn is_zero(value: &i32) -> bool {
+    *value == 0
+}
+
+impl PluginSpec {
+    /// A spec calling the default `create_plugins` entry point.
+    pub fn from_path(path: impl Into<PathString>) -> Self {
+        PluginSpec {
+            path: path.into(),
+            entry_point: None,
+            mode: EntryMode::Multiple,
+            flags: Vec::new(),
+            sha256: None,
+            library_paths: Vec::new(),
+            enabled: true,
+            priority: 0,
+            config: None,
+            version: None,
+        }
+    }
+
+    pub fn entry_point(&self) -> &str {
+        self.entry_point.as_deref().unwrap_or("create_plugins")
+    }
+
+    /// Whether the default entry point is used, which also allows the C ABI.
+    pub fn is_default_entry_point(&self) -> bool {
+        self.entry_point.is_none() && self.mode == EntryMode::Multiple
+    }
+}
+
+/// The signature of a plugin entry point.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryMode {
+    /// `fn() -> Vec<Plugins>`
+    #[default]
+    Multiple,
+    /// `fn() -> Box<dyn Plugin>`, registered as a base plugin.
+    Single,
 }
 
 /// Information about a loaded plugin, including the plugin itself and its group.
@@ -43,6 +211,11 @@ pub trait Plugin: Send + Sync + Any {
     fn group(&self) -> String {
         String::from("BasePlugin")
     }
+
+    /// Called once before the plugin is dropped, when it is deregistered or
+    /// the `PluginManager` is dropped. The plugin's library is still loaded,
+    /// so this is the place to stop threads and close files or sockets.
+    fn on_unload(&self) {}
 }
 
 pub trait PluginInventory: Plugin {
@@ -57,27 +230,31 @@ pub trait PluginInventory: Plugin {
 
 impl Debug for dyn Plugin {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} {{ name: {} }}", Plugin::group(self), self.name())
+        let name = debug_name(|| self.name());
+        write!(f, "{} {{ name: {name} }}", Plugin::group(self))
     }
 }
 
 impl Debug for dyn PluginInventory {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{} {{ name: {} }}",
-            PluginInventory::group(self),
-            self.name()
-        )
+        let name = debug_name(|| self.name());
+        write!(f, "{} {{ name: {name} }}", PluginInventory::group(self))
     }
 }
 
+/// The plugin's name, or a placeholder when `name` panics.
+fn debug_name(name: impl FnOnce() -> String) -> String {
+    catch_panic("<unnamed>", name).unwrap_or_else(|_| String::from("<panicked>"))
+}
+
 pub enum Plugins {
     Base(Box<dyn Plugin>),
     Inventory(Box<dyn PluginInventory>),
 }
 
 impl Plugins {
+    /// The plugin's name. Panics raised by the plugin propagate; use
+    /// [`Plugins::try_name`] to catch them.
     pub fn name(&self) -> String {
         match self {
             Plugins::Base(base) => base.name(),
@@ -85,6 +262,11 @@ impl Plugins {
         }
     }
 
+    /// The plugin's name, with panics turned into `PluginError::PluginPanicked`.
+    pub fn try_name(&self) -> Result<String, PluginError> {
+        catch_panic("<unnamed>", || self.name())
+    }
+
     pub fn group_name(&self) -> String {
         match self {
             Plugins::Base(_) => String::from("Base"),
@@ -92,10 +274,33 @@ impl Plugins {
         }
     }
 
+    /// Executes the plugin, turning a panic into `PluginError::PluginPanicked`.
     pub fn execute(&self, context: &dyn Any) -> Result<(), Box<dyn std::error::Error>> {
-        match self {
+        let name = self.try_name()?;
+        catch_panic(&name, || match self {
             Plugins::Base(base) => base.execute(context),
             Plugins::Inventory(inventory) => inventory.execute(context),
+        })?
+    }
+
+    /// Calls `Plugin::on_unload`, turning a panic into `PluginError::PluginPanicked`.
+    pub fn on_unload(&self) -> Result<(), PluginError> {
+        let name = self.try_name()?;
+        catch_panic(&name, || match self {
+            Plugins::Base(base) => base.on_unload(),
+            Plugins::Inventory(inventory) => inventory.on_unload(),
+        })
+    }
+
+    /// Calls `PluginInventory::load` on inventory plugins, turning a panic into
+    /// `PluginError::PluginPanicked`. Does nothing for base plugins.
+    pub fn load(&self) -> Result<(), PluginError> {
+        match self {
+            Plugins::Base(_) => Ok(()),
+            Plugins::Inventory(inventory) => {
+                let name = self.try_name()?;
+                catch_panic(&name, || inventory.load())
+            }
         }
     }
 }
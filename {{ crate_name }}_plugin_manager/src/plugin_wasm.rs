@@ -0,0 +1,271 @@
+//! WebAssembly plugin backend (requires the `wasm` feature).
+//!
+//! A `PluginEntry` whose path ends in `.wasm` (or `.wat`) is loaded into a
+//! wasmtime sandbox instead of being opened as a shared library. The module
+//! gets no imports, so it cannot touch the host beyond the calls below, and
+//! every call runs with a fuel budget and a memory cap, see [`WasmLimits`].
+//!
+//! Guest modules are written with the `plugin_wasm_sdk` crate, which exports:
+//!
+//! | export           | signature                | purpose                                       |
+//! |------------------|--------------------------|-----------------------------------------------|
+//! | `memory`         | memory                   | linear memory shared with the host            |
+//! | `plugin_alloc`   | `(len: i32) -> i32`      | allocates a buffer for the host to write into |
+//! | `plugin_dealloc` | `(ptr: i32, len: i32)`   | frees a buffer returned to the host           |
+//! | `plugin_name`    | `() -> i64`              | packed `ptr << 32 \| len` of the UTF-8 name    |
+//! | `plugin_kind`    | `() -> i32`              | optional; `1` for inventory plugins           |
+//! | `plugin_execute` | `(ptr: i32, len: i32) -> i64` | `0`, or the packed error message         |
+//! | `plugin_load`    | `()`                     | optional; `PluginInventory::load`             |
+//! | `plugin_unload`  | `()`                     | optional; `Plugin::on_unload`                 |
+//!
+//! Contexts are passed as bytes, like for C ABI plugins. The host frees every
+//! packed buffer it is returned with `plugin_dealloc`, and rejects a buffer
+//! that does not lie within the module's memory.
+
+use crate::PluginManager;
+use crate::plugin_errors::PluginError;
+use crate::plugin_ffi::encode_context;
+use crate::plugin_types::{Plugin, PluginInventory, PluginName, Plugins};
+use std::any::Any;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use wasmtime::{
+    Config, Engine, Instance, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder,
+};
+
+/// Resource limits applied to every WASM plugin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WasmLimits {
+    /// Fuel available to each call; roughly one unit per instruction.
+    pub fuel: u64,
+    /// Maximum size of the plugin's linear memory in bytes.
+    pub max_memory_bytes: usize,
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        WasmLimits {
+            fuel: 10_000_000,
+            max_memory_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+impl PluginManager {
+    /// Sets the limits applied to WASM plugins loaded from now on.
+    pub fn with_wasm_limits(mut self, limits: WasmLimits) -> Self {
+        self.wasm_limits = limits;
+        self
+    }
+}
+
+/// Compiles and instantiates the module at `path`.
+pub fn load_wasm_plugins(
+    path: &Path,
+    limits: WasmLimits,
+) -> Result<Vec<Plugins>, Box<dyn std::error::Error>> {
+    let (sandbox, name, inventory) = instantiate(path, limits)
+        .map_err(|error| format!("Unable to load WASM plugin {}: {error:#}", path.display()))?;
+    log::debug!("Loaded WASM plugin '{name}' from {}", path.display());
+
+    let plugin = WasmPlugin {
+        name,
+        path: path.to_path_buf(),
+        sandbox: Mutex::new(sandbox),
+    };
+    Ok(vec![if inventory {
+        Plugins::Inventory(Box::new(plugin))
+    } else {
+        Plugins::Base(Box::new(plugin))
+    }])
+}
+
+/// Instantiates the module and reads the plugin's name and kind.
+fn instantiate(path: &Path, limits: WasmLimits) -> wasmtime::Result<(Sandbox, PluginName, bool)> {
+    let mut config = Config::new();
+    config.consume_fuel(true);
+    let engine = Engine::new(&config)?;
+    let module = Module::from_file(&engine, path)?;
+
+    let mut store = Store::new(
+        &engine,
+        StoreLimitsBuilder::new()
+            .memory_size(limits.max_memory_bytes)
+            .instances(1)
+            .build(),
+    );
+    store.limiter(|state| state);
+    store.set_fuel(limits.fuel)?;
+    let instance = Linker::new(&engine).instantiate(&mut store, &module)?;
+    let memory = instance
+        .get_memory(&mut store, "memory")
+        .ok_or_else(|| wasmtime::Error::msg("the module does not export its memory"))?;
+
+    let mut sandbox = Sandbox {
+        store,
+        instance,
+        memory,
+        limits,
+    };
+    let name = sandbox.call_string("plugin_name")?;
+    let inventory = match sandbox
+        .instance
+        .get_typed_func::<(), i32>(&mut sandbox.store, "plugin_kind")
+    {
+        Ok(kind) => {
+            sandbox.refuel()?;
+            kind.call(&mut sandbox.store, ())? == 1
+        }
+        Err(_) => false,
+    };
+    Ok((sandbox, name, inventory))
+}
+
+/// A module instance together with its store.
+struct Sandbox {
+    store: Store<StoreLimits>,
+    instance: Instance,
+    memory: Memory,
+    limits: WasmLimits,
+}
+
+impl Sandbox {
+    /// Resets the fuel so every call gets the full budget.
+    fn refuel(&mut self) -> wasmtime::Result<()> {
+        self.store.set_fuel(self.limits.fuel)
+    }
+
+    /// Calls an export returning a packed `ptr << 32 | len` string.
+    fn call_string(&mut self, export: &str) -> wasmtime::Result<String> {
+        let function = self
+            .instance
+            .get_typed_func::<(), i64>(&mut self.store, export)?;
+        self.refuel()?;
+        let packed = function.call(&mut self.store, ())?;
+        self.take_packed(packed)
+    }
+
+    /// Reads a packed string and hands its buffer back to `plugin_dealloc`.
+    fn take_packed(&mut self, packed: i64) -> wasmtime::Result<String> {
+        let (ptr, len) = ((packed >> 32) as u32 as usize, packed as u32 as usize);
+        let bytes = ptr
+            .checked_add(len)
+            .and_then(|end| self.memory.data(&self.store).get(ptr..end))
+            .ok_or_else(|| {
+                wasmtime::Error::msg(format!(
+                    "the module returned a buffer outside its memory ({len} bytes at {ptr})"
+                ))
+            })?;
+        let string = String::from_utf8_lossy(bytes).into_owned();
+        let dealloc = self
+            .instance
+            .get_typed_func::<(i32, i32), ()>(&mut self.store, "plugin_dealloc")?;
+        dealloc.call(&mut self.store, ((packed >> 32) as i32, packed as i32))?;
+        Ok(string)
+    }
+
+    /// Copies `bytes` into a buffer allocated by the guest, which takes
+    /// ownership of it.
+    fn write_bytes(&mut self, bytes: &[u8]) -> wasmtime::Result<(i32, i32)> {
+        let len = i32::try_from(bytes.len()).map_err(|_| {
+            wasmtime::Error::msg(format!(
+                "a context of {} bytes does not fit in the module's memory",
+                bytes.len()
+            ))
+        })?;
+        let alloc = self
+            .instance
+            .get_typed_func::<i32, i32>(&mut self.store, "plugin_alloc")?;
+        let ptr = alloc.call(&mut self.store, len)?;
+        self.memory
+            .write(&mut self.store, ptr as u32 as usize, bytes)?;
+        Ok((ptr, len))
+    }
+
+    /// Runs `plugin_execute`; the inner error is the guest's own error message.
+    fn execute(&mut self, context: &[u8]) -> wasmtime::Result<Result<(), String>> {
+        self.refuel()?;
+        let (ptr, len) = self.write_bytes(context)?;
+        let execute = self
+            .instance
+            .get_typed_func::<(i32, i32), i64>(&mut self.store, "plugin_execute")?;
+        let packed = execute.call(&mut self.store, (ptr, len))?;
+        if packed == 0 {
+            return Ok(Ok(()));
+        }
+        Ok(Err(self.take_packed(packed)?))
+    }
+
+    /// Calls an optional export taking and returning nothing.
+    fn call_optional(&mut self, export: &str) -> wasmtime::Result<()> {
+        if let Ok(function) = self
+            .instance
+            .get_typed_func::<(), ()>(&mut self.store, export)
+        {
+            self.refuel()?;
+            function.call(&mut self.store, ())?;
+        }
+        Ok(())
+    }
+}
+
+/// A plugin running inside a WASM sandbox.
+///
+/// Calls are serialized; a trap leaves the instance usable for the next call.
+pub struct WasmPlugin {
+    name: PluginName,
+    path: PathBuf,
+    sandbox: Mutex<Sandbox>,
+}
+
+impl WasmPlugin {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Sandbox> {
+        self.sandbox
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn trap(&self, error: wasmtime::Error) -> PluginError {
+        PluginError::WasmTrap {
+            plugin: self.name.clone(),
+            library: self.path.clone(),
+            message: format!("{error:#}"),
+        }
+    }
+}
+
+impl Plugin for WasmPlugin {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn execute(&self, context: &dyn Any) -> Result<(), Box<dyn std::error::Error>> {
+        let context = encode_context(&self.name, context)?;
+        match self.lock().execute(&context) {
+            Ok(result) => Ok(result?),
+            Err(error) => Err(self.trap(error).into()),
+        }
+    }
+
+    fn on_unload(&self) {
+        if let Err(error) = self.lock().call_optional("plugin_unload") {
+            log::error!("{}", self.trap(error));
+        }
+    }
+}
+
+impl PluginInventory for WasmPlugin {
+    fn load(&self) {
+        if let Err(error) = self.lock().call_optional("plugin_load") {
+            log::error!(
+                "Inventory plugin '{}' failed to load: {}",
+                self.name,
+                self.trap(error)
+            );
+        }
+    }
+}
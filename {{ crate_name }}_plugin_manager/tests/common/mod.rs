@@ -0,0 +1,57 @@
+//! Helpers shared by the unit tests and the integration tests.
+
+use std::path::{Path, PathBuf};
+
+pub fn manifest_path() -> String {
+    "../tests/plugin_mods/Cargo.toml".to_string()
+}
+
+pub fn make_file_path(module_name: &str) -> String {
+    let mut path_name = PathBuf::new();
+    let mut module_name_prefix = String::from(std::env::consts::DLL_PREFIX);
+    module_name_prefix.push_str(module_name);
+    path_name.push("..");
+    path_name.push("target");
+    path_name.push("release");
+    path_name.push(module_name_prefix);
+    path_name.set_extension(std::env::consts::DLL_EXTENSION);
+    path_name.to_string_lossy().to_string()
+}
+
+/// A fresh temporary directory, removed when dropped, also when the test
+/// fails.
+#[allow(dead_code)]
+pub struct TestDir(PathBuf);
+
+#[allow(dead_code)]
+impl TestDir {
+    pub fn new(tag: &str) -> Self {
+        let path = std::fs::canonicalize(std::env::temp_dir())
+            .unwrap()
+            .join(format!("plugin_manager-{tag}-test-{}", std::process::id()));
+        // Left over by an aborted run.
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TestDir(path)
+    }
+}
+
+impl std::ops::Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TestDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,36 @@
+//! `plugin_tasks` is linked into this test binary, so its plugins are
+//! registered statically rather than loaded from its library.
+
+use plugin_manager::PluginManager;
+use plugin_manager::plugin_types::Plugin;
+use std::any::Any;
+
+use plugin_tasks as _;
+
+mod common;
+
+use common::{make_file_path, manifest_path};
+
+#[test]
+fn static_plugins_test() {
+    let plugin_manager = PluginManager::from_manifest(manifest_path())
+        .activate_plugins()
+        .unwrap();
+    assert_eq!(plugin_manager.plugins.len(), 4);
+
+    // The static plugin is the plugin crate's own type, not a library handle.
+    let task_a = plugin_manager.get_base_plugin("task_a").unwrap();
+    let task_a = task_a.as_ref() as &dyn Plugin as &dyn Any;
+    assert!(task_a.is::<plugin_tasks::task_a::TaskA>());
+    assert!(plugin_manager.get_plugin_library("task_a").is_none());
+    plugin_manager.execute_plugin("task_a", &()).unwrap();
+}
+
+#[test]
+#[should_panic(expected = "Plugin 'task_a' already registered")]
+fn static_plugins_duplicate_test() {
+    let _ = PluginManager::from_manifest(manifest_path())
+        .with_path(&make_file_path("plugin_tasks"), None)
+        .unwrap()
+        .activate_plugins();
+}